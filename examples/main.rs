@@ -1,13 +1,9 @@
 use serde::{Deserialize, Serialize};
-use std::{
-    sync::{Arc, Mutex},
-    thread,
-    time::Duration,
-};
-use xpress::{Xpress, XpressError};
+use std::{collections::HashMap, sync::Mutex, thread, time::Duration};
+use xpress::{handler, Json, Path, Query, Response, State, Xpress, XpressError};
 
 struct AppState {
-    users: Arc<Mutex<Vec<User>>>,
+    users: Mutex<Vec<User>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -27,9 +23,20 @@ const PORT: u16 = 8080;
 
 fn main() -> Result<(), XpressError> {
     let mut app = Xpress::new(&format!("127.0.0.1:{}", PORT));
-    let app_state = AppState {
-        users: Arc::new(Mutex::new(Vec::new())),
-    };
+    app.with_state(AppState {
+        users: Mutex::new(Vec::new()),
+    })?;
+
+    app.use_middleware(|req, res, next| {
+        println!(
+            "#{} {} {} from {:?}",
+            req.connection_id(),
+            req.method(),
+            req.path(),
+            req.client_ip()
+        );
+        next.run(req, res)
+    });
 
     app.get("/", |_req, res| match res.html("examples/hello.html") {
         Ok(_) => Ok(()),
@@ -40,12 +47,13 @@ fn main() -> Result<(), XpressError> {
         }
     });
 
-    app.get("/test/:id", |req, res| {
-        let id = req.params.get("id").unwrap();
-        let foo = req.query.get("foo").unwrap();
-        res.send(format!("{} {}", id.clone(), foo.clone()))?;
-        Ok(())
-    });
+    app.route(
+        "GET",
+        "/test/:id",
+        |Path(id): Path<String>, Query(query): Query<HashMap<String, String>>| {
+            format!("{} {}", id, query.get("foo").cloned().unwrap_or_default())
+        },
+    );
 
     app.get("/delay", |_req, res| {
         thread::sleep(Duration::from_secs(10));
@@ -53,43 +61,36 @@ fn main() -> Result<(), XpressError> {
         Ok(())
     });
 
-    let users_get = Arc::clone(&app_state.users);
-    app.get("/users", move |_req, res| match users_get.lock() {
-        Ok(users) => {
-            res.json(&*users)?;
-            Ok(())
-        }
-        Err(_) => {
-            res.status(500);
-            res.send("Internal Server Error!")?;
-            Ok(())
+    app.get("/users", |req, res| {
+        match req.state::<AppState>().unwrap().users.lock() {
+            Ok(users) => {
+                res.json(&*users)?;
+                Ok(())
+            }
+            Err(_) => {
+                res.status(500);
+                res.send("Internal Server Error!")?;
+                Ok(())
+            }
         }
     });
 
-    let users_post = Arc::clone(&app_state.users);
-
-    app.post("/users", move |req, res| {
-        match req.from_json::<User>() {
-            Ok(user) => {
-                let mut users = users_post
-                    .lock()
-                    .map_err(|_| XpressError::MutexError("Failed to acquire lock".to_string()))?;
+    app.post(
+        "/users",
+        handler(
+            |State(state): State<AppState>, Json(user): Json<User>, res: &mut Response| {
+                let mut users = state.users.lock()?;
+                if users.iter().any(|u| u.email == user.email) {
+                    return Err(XpressError::conflict("Email already registered"));
+                }
                 users.push(user.clone());
-                let response = UserRes {
+                res.json(&UserRes {
                     message: "User created".to_string(),
                     user,
-                };
-                res.json(&response)?;
-            }
-
-            Err(_) => {
-                res.status(400);
-                res.send("Invalid user data")?;
-            }
-        }
-
-        Ok(())
-    });
+                })
+            },
+        ),
+    );
 
     println!("Server running on port {}", PORT);
     app.listen()?;
@@ -0,0 +1,334 @@
+use std::io::{Cursor, Read};
+
+use flate2::{
+    read::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+
+use crate::{method::Method, middleware::Next, request::Request, response::Response, XpressError};
+
+// Brotli's highest qualities are far too slow for responses built per request.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const BUFFER_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// In order of preference when the client accepts several equally.
+    const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn encoder(self, reader: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+        match self {
+            Encoding::Brotli => Box::new(brotli::CompressorReader::new(
+                reader,
+                BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            )),
+            Encoding::Gzip => Box::new(GzEncoder::new(reader, Compression::default())),
+            // HTTP's "deflate" is the zlib format
+            Encoding::Deflate => Box::new(ZlibEncoder::new(reader, Compression::default())),
+        }
+    }
+}
+
+/// Options for the [`compression`] middleware.
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    min_size: usize,
+    brotli: bool,
+    gzip: bool,
+    deflate: bool,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompressionConfig {
+    /// Defaults to brotli, gzip and deflate for bodies of at least 1 KiB.
+    pub fn new() -> Self {
+        Self {
+            min_size: 1024,
+            brotli: true,
+            gzip: true,
+            deflate: true,
+        }
+    }
+
+    /// Smallest body worth compressing. Streamed bodies of unknown length
+    /// are always compressed.
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    pub fn brotli(mut self, enabled: bool) -> Self {
+        self.brotli = enabled;
+        self
+    }
+
+    pub fn gzip(mut self, enabled: bool) -> Self {
+        self.gzip = enabled;
+        self
+    }
+
+    pub fn deflate(mut self, enabled: bool) -> Self {
+        self.deflate = enabled;
+        self
+    }
+
+    fn enabled(&self, encoding: Encoding) -> bool {
+        match encoding {
+            Encoding::Brotli => self.brotli,
+            Encoding::Gzip => self.gzip,
+            Encoding::Deflate => self.deflate,
+        }
+    }
+
+    /// Picks the enabled encoding with the highest q-value in an
+    /// `Accept-Encoding` header.
+    fn negotiate(&self, accept_encoding: &str) -> Option<Encoding> {
+        let mut preferences = Vec::new();
+        for item in accept_encoding.split(',') {
+            let mut params = item.split(';');
+            let coding = params.next().unwrap_or("").trim().to_ascii_lowercase();
+            let mut q = 1.0;
+            for param in params {
+                if let Some((key, value)) = param.split_once('=') {
+                    if key.trim().eq_ignore_ascii_case("q") {
+                        q = value.trim().parse().unwrap_or(0.0);
+                    }
+                }
+            }
+            preferences.push((coding, q));
+        }
+
+        let quality = |encoding: Encoding| {
+            let find = |coding: &str| preferences.iter().find(|(c, _)| c == coding);
+            find(encoding.as_str())
+                .or_else(|| find("*"))
+                .map_or(0.0, |(_, q)| *q)
+        };
+
+        let mut best: Option<(Encoding, f32)> = None;
+        for encoding in Encoding::ALL {
+            let q = quality(encoding);
+            if self.enabled(encoding) && q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((encoding, q));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    fn compress(&self, req: &Request, res: &mut Response) -> Result<(), XpressError> {
+        if matches!(res.status, 100..=199 | 204 | 304) || res.header("Content-Encoding").is_some() {
+            return Ok(());
+        }
+        if !res.header("Content-Type").is_some_and(compressible) {
+            return Ok(());
+        }
+        if res
+            .header("Cache-Control")
+            .is_some_and(|value| value.to_ascii_lowercase().contains("no-transform"))
+        {
+            return Ok(());
+        }
+
+        // Whether or not this response ends up compressed, it depends on
+        // the request's Accept-Encoding
+        res.vary("Accept-Encoding");
+
+        let size = match &res.stream {
+            Some(stream) => stream.length,
+            None => Some(res.body.len() as u64),
+        };
+        if req.method == Method::Head || size.is_some_and(|size| size < self.min_size as u64) {
+            return Ok(());
+        }
+        let Some(encoding) = req
+            .header("Accept-Encoding")
+            .and_then(|header| self.negotiate(header))
+        else {
+            return Ok(());
+        };
+
+        match res.stream.take() {
+            Some(stream) => res.set_stream(encoding.encoder(stream.reader), None),
+            None => {
+                let body = Box::new(Cursor::new(std::mem::take(&mut res.body)));
+                let mut compressed = Vec::new();
+                encoding.encoder(body).read_to_end(&mut compressed)?;
+                res.body = compressed;
+            }
+        }
+
+        res.headers
+            .retain(|key, _| !key.eq_ignore_ascii_case("Content-Length"));
+        res.headers.insert(
+            "Content-Encoding".to_string(),
+            encoding.as_str().to_string(),
+        );
+        // The compressed bytes differ, so a strong validator no longer holds
+        if let Some(etag) = res.header("ETag").filter(|etag| !etag.starts_with("W/")) {
+            let weak = format!("W/{}", etag);
+            res.headers
+                .retain(|key, _| !key.eq_ignore_ascii_case("ETag"));
+            res.headers.insert("ETag".to_string(), weak);
+        }
+        Ok(())
+    }
+}
+
+// Text formats compress well; images, video and archives already are.
+fn compressible(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    // Event streams must reach the client as each event is written
+    if media_type == "text/event-stream" {
+        return false;
+    }
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+        )
+}
+
+/// Middleware compressing response bodies with the best encoding the client
+/// accepts, for text-like content types. Works for buffered and streamed
+/// bodies; compressed files are sent whole rather than as byte ranges.
+///
+/// ```ignore
+/// app.use_middleware(compression(CompressionConfig::new().min_size(512)));
+/// ```
+pub fn compression(
+    config: CompressionConfig,
+) -> impl Fn(&mut Request, &mut Response, Next<'_>) -> Result<(), XpressError> + Send + Sync + 'static
+{
+    move |req, res, next| {
+        next.run(req, res)?;
+        config.compress(req, res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RequestBuilder, TestClient, Xpress};
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    fn client(config: CompressionConfig) -> TestClient {
+        let mut app = Xpress::new("127.0.0.1:0");
+        app.use_middleware(compression(config));
+        app.get("/json", |_req, res| res.json(&vec!["xpress"; 500]));
+        app.get("/small", |_req, res| res.send("tiny"));
+        app.get("/png", |_req, res| {
+            res.headers
+                .insert("Content-Type".to_string(), "image/png".to_string());
+            res.send(vec![0; 4096])
+        });
+        app.get("/stream", |_req, res| {
+            res.stream(Cursor::new("streamed ".repeat(100)));
+            Ok(())
+        });
+        TestClient::new(app)
+    }
+
+    fn get(client: &TestClient, path: &str, accept: &str) -> Response {
+        client.send(RequestBuilder::new("GET", path).header("Accept-Encoding", accept))
+    }
+
+    fn decode(res: &Response) -> Vec<u8> {
+        let mut out = Vec::new();
+        let body = &res.body[..];
+        match res.header("Content-Encoding") {
+            Some("gzip") => GzDecoder::new(body).read_to_end(&mut out),
+            Some("deflate") => ZlibDecoder::new(body).read_to_end(&mut out),
+            Some("br") => brotli::Decompressor::new(body, 4096).read_to_end(&mut out),
+            _ => return res.body.clone(),
+        }
+        .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let config = CompressionConfig::new();
+        assert_eq!(
+            config.negotiate("gzip, deflate, br"),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(config.negotiate("gzip;q=1, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(config.negotiate("br;q=0, *;q=0.1"), Some(Encoding::Gzip));
+        assert_eq!(config.negotiate("identity"), None);
+        assert_eq!(config.negotiate("gzip;q=0"), None);
+        assert_eq!(
+            config.brotli(false).negotiate("br, deflate"),
+            Some(Encoding::Deflate)
+        );
+    }
+
+    #[test]
+    fn test_compresses_buffered_bodies() {
+        let client = client(CompressionConfig::new());
+        let plain = client.get("/json").body;
+
+        for accept in ["gzip", "deflate", "br"] {
+            let res = get(&client, "/json", accept);
+            assert_eq!(res.header("Content-Encoding"), Some(accept));
+            assert_eq!(res.headers["Vary"], "Accept-Encoding");
+            assert!(res.body.len() < plain.len());
+            assert_eq!(decode(&res), plain);
+        }
+    }
+
+    #[test]
+    fn test_skips_small_and_binary_bodies() {
+        let client = client(CompressionConfig::new());
+        let res = get(&client, "/small", "gzip");
+        assert_eq!(res.header("Content-Encoding"), None);
+        assert_eq!(res.headers["Vary"], "Accept-Encoding");
+
+        let res = get(&client, "/png", "gzip");
+        assert_eq!(res.header("Content-Encoding"), None);
+        assert_eq!(res.header("Vary"), None);
+
+        let res = client.get("/json");
+        assert_eq!(res.header("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn test_compresses_streamed_bodies() {
+        let client = client(CompressionConfig::new());
+        let res = get(&client, "/stream", "gzip");
+        assert_eq!(res.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(decode(&res), "streamed ".repeat(100).as_bytes());
+    }
+}
@@ -0,0 +1,299 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::{method::Method, request::Request, response::Response};
+
+/// A weak ETag derived from the body's content.
+pub(crate) fn content_etag(body: &[u8]) -> String {
+    let hash = Sha256::digest(body);
+    let hex: String = hash[..12].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("W/\"{}\"", hex)
+}
+
+/// An ETag derived from a file's size and modification time, so files do
+/// not have to be read to compute it. It is strong so that it can be used
+/// with `If-Range`.
+pub(crate) fn file_etag(len: u64, modified: SystemTime) -> String {
+    let modified = modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO);
+    format!("\"{:x}-{:x}\"", len, modified.as_secs())
+}
+
+/// Adds an automatic ETag if enabled and evaluates the request's
+/// preconditions against the response's validators, turning successful
+/// `GET` and `HEAD` responses into `304 Not Modified` or
+/// `412 Precondition Failed` as RFC 9110 §13.2.2 describes.
+pub(crate) fn apply(req: &Request, res: &mut Response, auto_etag: bool) {
+    if !matches!(req.method, Method::Get | Method::Head)
+        || !(200..300).contains(&res.status)
+        || res.events.is_some()
+    {
+        return;
+    }
+
+    if auto_etag && res.status == 200 && res.stream.is_none() && res.header("ETag").is_none() {
+        res.headers
+            .insert("ETag".to_string(), content_etag(&res.body));
+    }
+
+    let last_modified = res.header("Last-Modified").and_then(parse_date);
+    let status = evaluate(req, res.header("ETag"), last_modified, true);
+    match status {
+        Some(304) => {
+            res.status = 304;
+            res.body.clear();
+            res.stream = None;
+            res.headers
+                .retain(|key, _| !key.eq_ignore_ascii_case("Content-Type"));
+        }
+        Some(status) => {
+            res.status = status;
+            res.body.clear();
+            res.stream = None;
+        }
+        None => {}
+    }
+}
+
+/// Evaluates the conditional headers of `req`, returning the status to
+/// respond with when a precondition decides the response. `exists` tells
+/// whether the target currently has a representation, for `If-Match: *`.
+pub(crate) fn evaluate(
+    req: &Request,
+    etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+    exists: bool,
+) -> Option<u16> {
+    let safe = matches!(req.method, Method::Get | Method::Head);
+
+    if let Some(if_match) = req.header("If-Match") {
+        if !matches_any(if_match, etag, exists, true) {
+            return Some(412);
+        }
+    } else if let (Some(since), Some(modified)) = (
+        req.header("If-Unmodified-Since").and_then(parse_date),
+        last_modified,
+    ) {
+        if truncate(modified) > since {
+            return Some(412);
+        }
+    }
+
+    if let Some(if_none_match) = req.header("If-None-Match") {
+        if matches_any(if_none_match, etag, exists, false) {
+            return Some(if safe { 304 } else { 412 });
+        }
+    } else if let (true, Some(since), Some(modified)) = (
+        safe,
+        req.header("If-Modified-Since").and_then(parse_date),
+        last_modified,
+    ) {
+        if truncate(modified) <= since {
+            return Some(304);
+        }
+    }
+
+    None
+}
+
+// Whether a list of entity tags, or `*`, matches the current ETag.
+fn matches_any(header: &str, etag: Option<&str>, exists: bool, strong: bool) -> bool {
+    if header.trim() == "*" {
+        return exists;
+    }
+    let Some(current) = etag.and_then(parse_etag) else {
+        return false;
+    };
+    entity_tags(header).any(|(weak, tag)| {
+        if strong {
+            !weak && !current.0 && tag == current.1
+        } else {
+            tag == current.1
+        }
+    })
+}
+
+// Splits `W/"a", "b"` into `(weak, opaque-tag)` pairs. Tags may contain
+// commas, so this scans quoted strings rather than splitting.
+fn entity_tags(header: &str) -> impl Iterator<Item = (bool, &str)> {
+    let mut rest = header;
+    std::iter::from_fn(move || loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        if rest.is_empty() {
+            return None;
+        }
+        let (weak, after) = match rest.strip_prefix("W/") {
+            Some(after) => (true, after),
+            None => (false, rest),
+        };
+        let Some(quoted) = after.strip_prefix('"') else {
+            // Skip anything malformed up to the next comma
+            rest = rest.find(',').map_or("", |i| &rest[i + 1..]);
+            continue;
+        };
+        let end = quoted.find('"').unwrap_or(quoted.len());
+        rest = quoted.get(end + 1..).unwrap_or("");
+        return Some((weak, &quoted[..end]));
+    })
+}
+
+fn parse_etag(etag: &str) -> Option<(bool, &str)> {
+    let mut tags = entity_tags(etag);
+    tags.next()
+}
+
+pub(crate) fn parse_date(date: &str) -> Option<SystemTime> {
+    httpdate::parse_http_date(date.trim()).ok()
+}
+
+// HTTP dates have one second resolution.
+fn truncate(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, headers: &[(&str, &str)]) -> Request {
+        let mut req = Request {
+            method,
+            ..Request::default()
+        };
+        for (key, value) in headers {
+            req.headers.insert(key.to_string(), value.to_string());
+        }
+        req
+    }
+
+    fn response(etag: &str, last_modified: &str) -> Response {
+        let mut res = Response::new();
+        res.send("data").unwrap();
+        res.headers.insert("ETag".into(), etag.into());
+        res.headers
+            .insert("Last-Modified".into(), last_modified.into());
+        res
+    }
+
+    const LAST_MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+    #[test]
+    fn test_entity_tags() {
+        let tags: Vec<_> = entity_tags(r#"W/"a", "b,c" ,junk, "d""#).collect();
+        assert_eq!(tags, [(true, "a"), (false, "b,c"), (false, "d")]);
+    }
+
+    #[test]
+    fn test_auto_etag_and_if_none_match() {
+        let mut res = Response::new();
+        res.send("hello").unwrap();
+        apply(&request(Method::Get, &[]), &mut res, true);
+        let etag = res.headers["ETag"].clone();
+        assert!(etag.starts_with("W/\""));
+
+        let mut res = Response::new();
+        res.send("hello").unwrap();
+        // Weak comparison, so a strong tag with the same opaque value matches
+        let strong = etag.trim_start_matches("W/").to_string();
+        apply(
+            &request(
+                Method::Get,
+                &[("If-None-Match", &format!("\"x\", {}", strong))],
+            ),
+            &mut res,
+            true,
+        );
+        assert_eq!(res.status, 304);
+        assert!(res.body.is_empty());
+        assert_eq!(res.headers["ETag"], etag);
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let not_modified = [("If-Modified-Since", LAST_MODIFIED)];
+        let mut res = response("\"v1\"", LAST_MODIFIED);
+        apply(&request(Method::Get, &not_modified), &mut res, false);
+        assert_eq!(res.status, 304);
+
+        let modified = [("If-Modified-Since", "Wed, 21 Oct 2015 07:27:59 GMT")];
+        let mut res = response("\"v1\"", LAST_MODIFIED);
+        apply(&request(Method::Get, &modified), &mut res, false);
+        assert_eq!(res.status, 200);
+
+        // If-None-Match takes precedence over If-Modified-Since
+        let both = [
+            ("If-None-Match", "\"v0\""),
+            ("If-Modified-Since", LAST_MODIFIED),
+        ];
+        let mut res = response("\"v1\"", LAST_MODIFIED);
+        apply(&request(Method::Get, &both), &mut res, false);
+        assert_eq!(res.status, 200);
+    }
+
+    #[test]
+    fn test_if_match_uses_strong_comparison() {
+        let mut res = response("\"v1\"", LAST_MODIFIED);
+        apply(
+            &request(Method::Get, &[("If-Match", "\"v1\"")]),
+            &mut res,
+            false,
+        );
+        assert_eq!(res.status, 200);
+
+        let mut res = response("W/\"v1\"", LAST_MODIFIED);
+        apply(
+            &request(Method::Get, &[("If-Match", "W/\"v1\"")]),
+            &mut res,
+            false,
+        );
+        assert_eq!(res.status, 412);
+
+        let mut res = response("\"v1\"", LAST_MODIFIED);
+        apply(&request(Method::Get, &[("If-Match", "*")]), &mut res, false);
+        assert_eq!(res.status, 200);
+    }
+
+    #[test]
+    fn test_if_unmodified_since() {
+        let headers = [("If-Unmodified-Since", "Wed, 21 Oct 2015 07:27:59 GMT")];
+        let mut res = response("\"v1\"", LAST_MODIFIED);
+        apply(&request(Method::Get, &headers), &mut res, false);
+        assert_eq!(res.status, 412);
+    }
+
+    #[test]
+    fn test_unsafe_methods_and_errors_are_untouched() {
+        let mut res = response("\"v1\"", LAST_MODIFIED);
+        apply(
+            &request(Method::Post, &[("If-None-Match", "*")]),
+            &mut res,
+            false,
+        );
+        assert_eq!(res.status, 200);
+
+        let mut res = response("\"v1\"", LAST_MODIFIED);
+        res.status = 404;
+        apply(
+            &request(Method::Get, &[("If-None-Match", "*")]),
+            &mut res,
+            false,
+        );
+        assert_eq!(res.status, 404);
+    }
+
+    #[test]
+    fn test_evaluate_for_unsafe_method() {
+        let req = request(Method::Put, &[("If-Match", "\"v1\"")]);
+        assert_eq!(evaluate(&req, Some("\"v2\""), None, true), Some(412));
+        assert_eq!(evaluate(&req, Some("\"v1\""), None, true), None);
+
+        let req = request(Method::Put, &[("If-None-Match", "*")]);
+        assert_eq!(evaluate(&req, Some("\"v1\""), None, true), Some(412));
+        assert_eq!(evaluate(&req, None, None, false), None);
+    }
+}
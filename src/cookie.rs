@@ -0,0 +1,251 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use crate::XpressError;
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        })
+    }
+}
+
+/// A cookie sent to the client with [`Response::set_cookie`](crate::Response::set_cookie).
+///
+/// ```ignore
+/// res.set_cookie(
+///     Cookie::new("theme", "dark")
+///         .path("/")
+///         .http_only()
+///         .same_site(SameSite::Lax)
+///         .max_age(Duration::from_secs(3600)),
+/// )?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub(crate) fn with_value(mut self, value: String) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    pub fn http_only(mut self) -> Self {
+        self.http_only = true;
+        self
+    }
+
+    /// Sets `SameSite`. `SameSite::None` also marks the cookie `Secure`, as
+    /// browsers require.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        if same_site == SameSite::None {
+            self.secure = true;
+        }
+        self
+    }
+
+    /// Turns the cookie into one that makes the client delete it. Path and
+    /// domain must match the cookie being removed.
+    pub fn expire(mut self) -> Self {
+        self.value.clear();
+        self.max_age = Some(Duration::ZERO);
+        self.expires = Some(SystemTime::UNIX_EPOCH);
+        self
+    }
+
+    /// Checks the name is a token and the value holds only cookie-octets
+    /// (RFC 6265), so the header cannot be split or injected into.
+    pub(crate) fn validate(&self) -> Result<(), XpressError> {
+        let name_ok = !self.name.is_empty()
+            && self
+                .name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+        let value_ok = self
+            .value
+            .bytes()
+            .all(|b| matches!(b, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E));
+        let attrs_ok = [&self.path, &self.domain]
+            .into_iter()
+            .flatten()
+            .all(|attr| !attr.bytes().any(|b| b == b';' || b.is_ascii_control()));
+
+        if name_ok && value_ok && attrs_ok {
+            Ok(())
+        } else {
+            Err(XpressError::Custom(format!(
+                "Invalid cookie {:?}",
+                self.name
+            )))
+        }
+    }
+}
+
+impl fmt::Display for Cookie {
+    /// Formats the cookie as a `Set-Cookie` header value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", httpdate::fmt_http_date(expires))?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a `Cookie` request header into name/value pairs. Malformed pairs
+/// are skipped and the first occurrence of a name wins.
+pub(crate) fn parse_cookie_header(header: &str) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+    for pair in header.split(';') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            continue;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        cookies
+            .entry(name.to_string())
+            .or_insert_with(|| value.to_string());
+    }
+    cookies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cookie_header() {
+        let cookies = parse_cookie_header("theme=dark; session=\"abc\"; broken; theme=light");
+        assert_eq!(cookies["theme"], "dark");
+        assert_eq!(cookies["session"], "abc");
+        assert_eq!(cookies.len(), 2);
+    }
+
+    #[test]
+    fn test_set_cookie_format() {
+        let cookie = Cookie::new("id", "42")
+            .path("/")
+            .http_only()
+            .same_site(SameSite::None)
+            .max_age(Duration::from_secs(60));
+        assert_eq!(
+            cookie.to_string(),
+            "id=42; Path=/; Max-Age=60; Secure; HttpOnly; SameSite=None"
+        );
+    }
+
+    #[test]
+    fn test_expire() {
+        let cookie = Cookie::new("id", "42").path("/").expire();
+        assert_eq!(
+            cookie.to_string(),
+            "id=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_injection() {
+        assert!(Cookie::new("id", "42").validate().is_ok());
+        assert!(Cookie::new("id", "a;b").validate().is_err());
+        assert!(Cookie::new("id", "a\r\nX: y").validate().is_err());
+        assert!(Cookie::new("bad name", "1").validate().is_err());
+        assert!(Cookie::new("id", "1").path("/;x").validate().is_err());
+    }
+}
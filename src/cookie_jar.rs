@@ -0,0 +1,308 @@
+use std::{collections::HashMap, sync::Arc};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{cookie::Cookie, extract::FromRequest, request::Request, XpressError};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const NONCE_LEN: usize = 12;
+
+/// A secret used to sign and encrypt cookies. Separate signing and
+/// encryption keys are derived from it.
+#[derive(Clone)]
+pub struct Key {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl Key {
+    /// Derives a key from a secret of at least 32 random bytes.
+    pub fn from_secret(secret: &[u8]) -> Result<Self, XpressError> {
+        if secret.len() < 32 {
+            return Err(XpressError::Custom(
+                "Cookie secret must be at least 32 bytes".to_string(),
+            ));
+        }
+        Ok(Self {
+            signing: derive(secret, b"xpress cookie signing"),
+            encryption: derive(secret, b"xpress cookie encryption"),
+        })
+    }
+
+    fn mac(&self, name: &str, value: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.signing)
+            .expect("HMAC accepts any key length");
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.encryption.into())
+    }
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+fn derive(secret: &[u8], label: &[u8]) -> [u8; 32] {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(label);
+    mac.finalize().into_bytes().into()
+}
+
+/// The current cookie key and older ones still accepted when reading.
+#[derive(Debug)]
+pub(crate) struct CookieKeys {
+    pub(crate) current: Key,
+    pub(crate) previous: Vec<Key>,
+}
+
+impl CookieKeys {
+    fn all(&self) -> impl Iterator<Item = &Key> {
+        std::iter::once(&self.current).chain(&self.previous)
+    }
+}
+
+/// The cookies of a request, with access to signed and encrypted cookies
+/// using the keys configured with [`Xpress::cookie_keys`](crate::Xpress::cookie_keys).
+///
+/// Cookies that fail verification or decryption are treated as absent.
+///
+/// ```ignore
+/// let jar = req.cookie_jar();
+/// let user = jar.signed().get("user_id");
+/// res.set_cookie(jar.private().seal(Cookie::new("token", "s3cret").http_only())?)?;
+/// ```
+#[derive(Debug)]
+pub struct CookieJar {
+    cookies: HashMap<String, String>,
+    keys: Option<Arc<CookieKeys>>,
+}
+
+impl CookieJar {
+    pub(crate) fn new(cookies: HashMap<String, String>, keys: Option<Arc<CookieKeys>>) -> Self {
+        Self { cookies, keys }
+    }
+
+    /// A plain, unverified cookie.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies.get(name).map(String::as_str)
+    }
+
+    /// Cookies whose value is readable but protected against tampering.
+    pub fn signed(&self) -> SignedJar<'_> {
+        SignedJar(self)
+    }
+
+    /// Cookies whose value is encrypted and authenticated.
+    pub fn private(&self) -> PrivateJar<'_> {
+        PrivateJar(self)
+    }
+
+    fn keys(&self) -> Result<&CookieKeys, XpressError> {
+        self.keys
+            .as_deref()
+            .ok_or_else(|| XpressError::Custom("No cookie keys configured".to_string()))
+    }
+}
+
+impl FromRequest for CookieJar {
+    fn from_request(req: &Request) -> Result<Self, XpressError> {
+        Ok(req.cookie_jar())
+    }
+}
+
+/// Signed view of a [`CookieJar`]. Values are stored as
+/// `base64(value).base64(hmac)`, with the HMAC covering the cookie name.
+pub struct SignedJar<'a>(&'a CookieJar);
+
+impl SignedJar<'_> {
+    pub fn get(&self, name: &str) -> Option<String> {
+        let keys = self.0.keys.as_deref()?;
+        let (value, tag) = self.0.get(name)?.split_once('.')?;
+        let value = String::from_utf8(URL_SAFE_NO_PAD.decode(value).ok()?).ok()?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+
+        keys.all()
+            .any(|key| key.mac(name, &value).verify_slice(&tag).is_ok())
+            .then_some(value)
+    }
+
+    /// Signs the cookie's value with the current key.
+    pub fn seal(&self, cookie: Cookie) -> Result<Cookie, XpressError> {
+        let key = &self.0.keys()?.current;
+        let tag = key
+            .mac(cookie.name(), cookie.value())
+            .finalize()
+            .into_bytes();
+        let value = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(cookie.value()),
+            URL_SAFE_NO_PAD.encode(tag)
+        );
+        Ok(cookie.with_value(value))
+    }
+}
+
+/// Encrypted view of a [`CookieJar`]. Values are AES-256-GCM encrypted with
+/// the cookie name as associated data, so they cannot be moved between
+/// cookies.
+pub struct PrivateJar<'a>(&'a CookieJar);
+
+impl PrivateJar<'_> {
+    pub fn get(&self, name: &str) -> Option<String> {
+        let keys = self.0.keys.as_deref()?;
+        let sealed = URL_SAFE_NO_PAD.decode(self.0.get(name)?).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = || Payload {
+            msg: ciphertext,
+            aad: name.as_bytes(),
+        };
+
+        keys.all()
+            .find_map(|key| {
+                key.cipher()
+                    .decrypt(Nonce::from_slice(nonce), payload())
+                    .ok()
+            })
+            .and_then(|plain| String::from_utf8(plain).ok())
+    }
+
+    /// Encrypts the cookie's value with the current key.
+    pub fn seal(&self, cookie: Cookie) -> Result<Cookie, XpressError> {
+        let key = &self.0.keys()?.current;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = key
+            .cipher()
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: cookie.value().as_bytes(),
+                    aad: cookie.name().as_bytes(),
+                },
+            )
+            .map_err(|_| XpressError::Custom("Cookie encryption failed".to_string()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(cookie.with_value(URL_SAFE_NO_PAD.encode(sealed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> Key {
+        Key::from_secret(&[byte; 32]).unwrap()
+    }
+
+    fn jar(keys: CookieKeys, cookies: &[&Cookie]) -> CookieJar {
+        CookieJar {
+            cookies: cookies
+                .iter()
+                .map(|c| (c.name().to_string(), c.value().to_string()))
+                .collect(),
+            keys: Some(Arc::new(keys)),
+        }
+    }
+
+    fn keys(current: u8, previous: &[u8]) -> CookieKeys {
+        CookieKeys {
+            current: key(current),
+            previous: previous.iter().map(|b| key(*b)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_short_secret_is_rejected() {
+        assert!(Key::from_secret(b"too short").is_err());
+    }
+
+    #[test]
+    fn test_signed_round_trip_and_tampering() {
+        let sealed = jar(keys(1, &[]), &[])
+            .signed()
+            .seal(Cookie::new("user", "42; admin"))
+            .unwrap();
+        assert!(sealed.validate().is_ok());
+        assert_eq!(
+            jar(keys(1, &[]), &[&sealed])
+                .signed()
+                .get("user")
+                .as_deref(),
+            Some("42; admin")
+        );
+
+        let (_, tag) = sealed.value().split_once('.').unwrap();
+        let forged = Cookie::new(
+            "user",
+            format!("{}.{}", URL_SAFE_NO_PAD.encode("1; admin"), tag),
+        );
+        assert_eq!(jar(keys(1, &[]), &[&forged]).signed().get("user"), None);
+
+        // The signature is bound to the cookie name
+        let renamed = Cookie::new("other", sealed.value());
+        assert_eq!(jar(keys(1, &[]), &[&renamed]).signed().get("other"), None);
+    }
+
+    #[test]
+    fn test_private_round_trip() {
+        let sealed = jar(keys(1, &[]), &[])
+            .private()
+            .seal(Cookie::new("token", "s3cret"))
+            .unwrap();
+        assert!(!sealed.value().contains("s3cret"));
+        assert_eq!(
+            jar(keys(1, &[]), &[&sealed])
+                .private()
+                .get("token")
+                .as_deref(),
+            Some("s3cret")
+        );
+        assert_eq!(jar(keys(2, &[]), &[&sealed]).private().get("token"), None);
+        assert_eq!(jar(keys(1, &[]), &[&sealed]).signed().get("token"), None);
+    }
+
+    #[test]
+    fn test_rotated_keys_still_verify() {
+        let signed = jar(keys(1, &[]), &[])
+            .signed()
+            .seal(Cookie::new("a", "1"))
+            .unwrap();
+        let private = jar(keys(1, &[]), &[])
+            .private()
+            .seal(Cookie::new("b", "2"))
+            .unwrap();
+
+        let rotated = jar(keys(2, &[1]), &[&signed, &private]);
+        assert_eq!(rotated.signed().get("a").as_deref(), Some("1"));
+        assert_eq!(rotated.private().get("b").as_deref(), Some("2"));
+
+        let dropped = jar(keys(2, &[]), &[&signed, &private]);
+        assert_eq!(dropped.signed().get("a"), None);
+        assert_eq!(dropped.private().get("b"), None);
+    }
+
+    #[test]
+    fn test_seal_without_keys_fails() {
+        let jar = Request::default().cookie_jar();
+        assert!(jar.signed().seal(Cookie::new("a", "1")).is_err());
+        assert_eq!(jar.signed().get("a"), None);
+    }
+}
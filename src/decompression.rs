@@ -0,0 +1,161 @@
+use std::io::Read;
+
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+
+use crate::{middleware::Next, request::Request, response::Response, XpressError};
+
+/// Middleware decoding `gzip` and `deflate` request bodies, so handlers and
+/// extractors see the original bytes. Bodies over `max_size` bytes, before
+/// or after inflating, are rejected with `413`, corrupt ones with `400` and
+/// other encodings with `415`.
+///
+/// The compressed body has already been read under
+/// [`Xpress::max_body_size`](crate::Xpress::max_body_size), which bounds its
+/// size in memory. Register this before any middleware that reads the body,
+/// such as body parsers, so they see the decoded bytes.
+///
+/// ```ignore
+/// app.max_body_size(10 * 1024 * 1024);
+/// app.use_middleware(decompression(10 * 1024 * 1024));
+/// ```
+pub fn decompression(
+    max_size: usize,
+) -> impl Fn(&mut Request, &mut Response, Next<'_>) -> Result<(), XpressError> + Send + Sync + 'static
+{
+    move |req, res, next| {
+        decode_body(req, max_size)?;
+        next.run(req, res)
+    }
+}
+
+fn decode_body(req: &mut Request, max_size: usize) -> Result<(), XpressError> {
+    let Some(header) = req.header("Content-Encoding") else {
+        return Ok(());
+    };
+    let codings: Vec<String> = header
+        .split(',')
+        .map(|coding| coding.trim().to_ascii_lowercase())
+        .filter(|coding| !coding.is_empty() && coding != "identity")
+        .collect();
+    if !codings.is_empty() && req.body.len() > max_size {
+        return Err(XpressError::payload_too_large());
+    }
+
+    let mut body = std::mem::take(&mut req.body);
+    // Codings are listed in the order they were applied
+    for coding in codings.iter().rev() {
+        body = match coding.as_str() {
+            "gzip" | "x-gzip" => inflate(GzDecoder::new(&body[..]), max_size)?,
+            "deflate" if is_zlib(&body) => inflate(ZlibDecoder::new(&body[..]), max_size)?,
+            // Some clients send raw deflate data without the zlib wrapper
+            "deflate" => inflate(DeflateDecoder::new(&body[..]), max_size)?,
+            _ => {
+                return Err(XpressError::unsupported_media_type(format!(
+                    "Unsupported Content-Encoding: {}",
+                    coding
+                )))
+            }
+        };
+    }
+
+    req.headers
+        .retain(|key, _| !key.eq_ignore_ascii_case("Content-Encoding"));
+    req.headers
+        .retain(|key, _| !key.eq_ignore_ascii_case("Content-Length"));
+    req.headers
+        .insert("Content-Length".to_string(), body.len().to_string());
+    req.body = body;
+    Ok(())
+}
+
+// Reads at most one byte past the limit, so a small compressed body can't
+// expand into an unbounded allocation.
+fn inflate(decoder: impl Read, max_size: usize) -> Result<Vec<u8>, XpressError> {
+    let mut body = Vec::new();
+    decoder
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|_| XpressError::bad_request("Malformed compressed request body"))?;
+    if body.len() > max_size {
+        return Err(XpressError::payload_too_large());
+    }
+    Ok(body)
+}
+
+// A zlib header is a CMF byte with the deflate method and a check value
+// making the first two bytes a multiple of 31.
+fn is_zlib(data: &[u8]) -> bool {
+    match data {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RequestBuilder, TestClient, Xpress};
+    use flate2::{
+        write::{DeflateEncoder, GzEncoder, ZlibEncoder},
+        Compression,
+    };
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn client() -> TestClient {
+        let mut app = Xpress::new("127.0.0.1:0");
+        app.use_middleware(decompression(1024));
+        app.post("/echo", |req, res| {
+            let value: serde_json::Value = req.from_json()?;
+            res.json(&value)
+        });
+        TestClient::new(app)
+    }
+
+    fn post(client: &TestClient, encoding: &str, body: Vec<u8>) -> Response {
+        client.send(
+            RequestBuilder::new("POST", "/echo")
+                .header("Content-Encoding", encoding)
+                .body(body),
+        )
+    }
+
+    #[test]
+    fn test_decodes_gzip_and_deflate() {
+        let client = client();
+        let json = br#"{"name":"xpress"}"#;
+
+        let res = post(&client, "gzip", gzip(json));
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body, json);
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(json).unwrap();
+        assert_eq!(post(&client, "deflate", zlib.finish().unwrap()).body, json);
+
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(json).unwrap();
+        assert_eq!(post(&client, "deflate", raw.finish().unwrap()).body, json);
+
+        assert_eq!(post(&client, "gzip, gzip", gzip(&gzip(json))).body, json);
+        assert_eq!(client.post("/echo", &json[..]).body, json);
+    }
+
+    #[test]
+    fn test_rejects_bombs_corrupt_and_unknown_encodings() {
+        let client = client();
+        let bomb = gzip(&[b' '; 1025]);
+        assert_eq!(post(&client, "gzip", bomb).status, 413);
+        // The compressed size is checked before anything is inflated
+        let mut large = gzip(b"{}");
+        large.resize(1025, 0);
+        assert_eq!(post(&client, "gzip", large).status, 413);
+        assert_eq!(post(&client, "gzip", b"not gzip".to_vec()).status, 400);
+        assert_eq!(post(&client, "br", b"{}".to_vec()).status, 415);
+    }
+}
@@ -1,4 +1,12 @@
-use std::fmt;
+use std::{error::Error, fmt};
+
+use crate::{
+    request::Request,
+    response::{reason_phrase, Response},
+};
+
+pub(crate) type ErrorHandler =
+    Box<dyn Fn(&XpressError, &Request, &mut Response) -> Result<(), XpressError> + Send + Sync>;
 
 #[derive(Debug)]
 pub enum XpressError {
@@ -9,9 +17,69 @@ pub enum XpressError {
     FileNotFound(String),
     ConnectionError(String),
     ParsingError(String),
+    TlsError(String),
+    Http(HttpError),
     Custom(String),
 }
 
+/// An error with an explicit HTTP status and a message that is safe to show
+/// to clients, optionally carrying structured details and the underlying
+/// cause.
+#[derive(Debug)]
+pub struct HttpError {
+    status: u16,
+    message: String,
+    details: Option<serde_json::Value>,
+    source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl HttpError {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            details: None,
+            source: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn with_source(mut self, source: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn details(&self) -> Option<&serde_json::Value> {
+        self.details.as_ref()
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for HttpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|err| err as &(dyn Error + 'static))
+    }
+}
+
 impl fmt::Display for XpressError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -22,6 +90,8 @@ impl fmt::Display for XpressError {
             XpressError::FileNotFound(path) => write!(f, "File Not Found: {}", path),
             XpressError::ConnectionError(msg) => write!(f, "Connection Error: {}", msg),
             XpressError::ParsingError(msg) => write!(f, "Parsing Error: {}", msg),
+            XpressError::TlsError(msg) => write!(f, "TLS Error: {}", msg),
+            XpressError::Http(err) => write!(f, "{}", err),
             XpressError::Custom(msg) => write!(f, "Error: {}", msg),
         }
     }
@@ -29,7 +99,11 @@ impl fmt::Display for XpressError {
 
 impl From<std::io::Error> for XpressError {
     fn from(err: std::io::Error) -> Self {
-        XpressError::IoError(err)
+        // Errors passed through `Read`/`Write` implementations keep their status
+        match err.downcast::<XpressError>() {
+            Ok(inner) => inner,
+            Err(err) => XpressError::IoError(err),
+        }
     }
 }
 
@@ -39,14 +113,234 @@ impl From<serde_json::Error> for XpressError {
     }
 }
 
+impl Error for XpressError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            XpressError::IoError(err) => Some(err),
+            XpressError::JsonError(err) => Some(err),
+            XpressError::Http(err) => err.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<HttpError> for XpressError {
+    fn from(err: HttpError) -> Self {
+        XpressError::Http(err)
+    }
+}
+
+impl<T> From<std::sync::PoisonError<T>> for XpressError {
+    fn from(err: std::sync::PoisonError<T>) -> Self {
+        XpressError::MutexError(err.to_string())
+    }
+}
+
+// Parsing a number or flag with `?` almost always means parsing request
+// input, such as a param or header, so it's the client's fault
+macro_rules! bad_request_from {
+    ($($err:ty),*) => {
+        $(
+            impl From<$err> for XpressError {
+                fn from(err: $err) -> Self {
+                    XpressError::bad_request(err.to_string()).with_source(err)
+                }
+            }
+        )*
+    };
+}
+
+bad_request_from!(
+    std::num::ParseIntError,
+    std::num::ParseFloatError,
+    std::str::ParseBoolError
+);
+
+// These also come from server data, such as stored files or configuration,
+// so request parsing maps them to `bad_request` explicitly
+macro_rules! internal_from {
+    ($($err:ty),*) => {
+        $(
+            impl From<$err> for XpressError {
+                fn from(err: $err) -> Self {
+                    XpressError::internal(err)
+                }
+            }
+        )*
+    };
+}
+
+internal_from!(
+    std::str::Utf8Error,
+    std::string::FromUtf8Error,
+    std::net::AddrParseError
+);
+
 impl XpressError {
+    /// An error with any status and a client-facing message.
+    pub fn http(status: u16, message: impl Into<String>) -> Self {
+        XpressError::Http(HttpError::new(status, message))
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::http(400, message)
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::http(401, reason_phrase(401))
+    }
+
+    pub fn forbidden() -> Self {
+        Self::http(403, reason_phrase(403))
+    }
+
+    pub fn not_found() -> Self {
+        Self::http(404, reason_phrase(404))
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::http(409, message)
+    }
+
+    pub fn payload_too_large() -> Self {
+        Self::http(413, reason_phrase(413))
+    }
+
+    pub fn unsupported_media_type(message: impl Into<String>) -> Self {
+        Self::http(415, message)
+    }
+
+    pub fn unprocessable(message: impl Into<String>) -> Self {
+        Self::http(422, message)
+    }
+
+    pub fn too_many_requests() -> Self {
+        Self::http(429, reason_phrase(429))
+    }
+
+    /// A 500 error wrapping its cause. The cause is logged but never shown
+    /// to clients.
+    pub fn internal(source: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        XpressError::Http(HttpError::new(500, reason_phrase(500)).with_source(source))
+    }
+
+    /// Attaches structured details, shown by
+    /// [`problem_json`](crate::problem_json) as the `details` member.
+    pub fn with_details(self, details: serde_json::Value) -> Self {
+        match self {
+            XpressError::Http(err) => XpressError::Http(err.with_details(details)),
+            other => other.into_http().with_details(details),
+        }
+    }
+
+    /// Attaches the underlying cause, returned by [`Error::source`].
+    pub fn with_source(self, source: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        match self {
+            XpressError::Http(err) => XpressError::Http(err.with_source(source)),
+            other => other.into_http().with_source(source),
+        }
+    }
+
+    pub fn details(&self) -> Option<&serde_json::Value> {
+        match self {
+            XpressError::Http(err) => err.details(),
+            _ => None,
+        }
+    }
+
+    fn into_http(self) -> Self {
+        XpressError::Http(HttpError::new(self.status_code(), self.to_string()).with_source(self))
+    }
+
     pub fn status_code(&self) -> u16 {
         match self {
+            XpressError::Http(err) => err.status,
             XpressError::NotFound(_) | XpressError::FileNotFound(_) => 404,
             XpressError::ParsingError(_) => 400,
             XpressError::ConnectionError(_) => 502,
-            XpressError::MutexError(_) => 500,
+            XpressError::MutexError(_) | XpressError::TlsError(_) => 500,
             XpressError::IoError(_) | XpressError::JsonError(_) | XpressError::Custom(_) => 500,
         }
     }
 }
+
+/// Renders errors as plain text. Messages of 5xx errors are replaced by the
+/// status' reason phrase so internal details never reach clients, and
+/// [`HttpError`] messages are sent as they are.
+pub(crate) fn default_error_handler(
+    err: &XpressError,
+    _req: &Request,
+    res: &mut Response,
+) -> Result<(), XpressError> {
+    let status = err.status_code();
+    if status >= 500 {
+        res.send(reason_phrase(status))
+    } else if let XpressError::Http(err) = err {
+        res.send(err.message())
+    } else {
+        res.send(format!("Error: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constructors_carry_status() {
+        assert_eq!(XpressError::unauthorized().status_code(), 401);
+        assert_eq!(XpressError::forbidden().status_code(), 403);
+        assert_eq!(XpressError::conflict("taken").status_code(), 409);
+        assert_eq!(XpressError::unprocessable("bad").status_code(), 422);
+        assert_eq!(XpressError::http(418, "teapot").status_code(), 418);
+        assert_eq!(XpressError::forbidden().to_string(), "Forbidden");
+    }
+
+    #[test]
+    fn test_from_parse_errors_is_bad_request() {
+        let err: XpressError = "abc".parse::<u32>().unwrap_err().into();
+        assert_eq!(err.status_code(), 400);
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn test_from_server_side_errors_is_internal() {
+        let err: XpressError = String::from_utf8(vec![0xFF]).unwrap_err().into();
+        assert_eq!(err.status_code(), 500);
+        let err: XpressError = "not an ip".parse::<std::net::IpAddr>().unwrap_err().into();
+        assert_eq!(err.status_code(), 500);
+    }
+
+    #[test]
+    fn test_io_error_keeps_wrapped_status() {
+        let err: XpressError = std::io::Error::other(XpressError::payload_too_large()).into();
+        assert_eq!(err.status_code(), 413);
+        let err: XpressError = std::io::Error::other("disk on fire").into();
+        assert!(matches!(err, XpressError::IoError(_)));
+    }
+
+    #[test]
+    fn test_internal_keeps_source_private() {
+        let cause = std::io::Error::other("disk on fire");
+        let err = XpressError::internal(cause);
+        assert_eq!(err.status_code(), 500);
+        assert_eq!(err.to_string(), "Internal Server Error");
+        assert_eq!(err.source().unwrap().to_string(), "disk on fire");
+
+        let mut res = Response::new();
+        default_error_handler(&err, &Request::default(), &mut res).unwrap();
+        assert_eq!(res.body, b"Internal Server Error");
+    }
+
+    #[test]
+    fn test_with_details_wraps_other_variants() {
+        let err = XpressError::ParsingError("bad id".into())
+            .with_details(serde_json::json!({"field": "id"}));
+        assert_eq!(err.status_code(), 400);
+        assert_eq!(err.details().unwrap()["field"], "id");
+        assert!(matches!(
+            err.source().and_then(|e| e.downcast_ref::<XpressError>()),
+            Some(XpressError::ParsingError(_))
+        ));
+    }
+}
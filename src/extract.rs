@@ -0,0 +1,388 @@
+use std::{collections::HashMap, ops::Deref, sync::Arc};
+
+use serde::de::DeserializeOwned;
+
+use crate::{
+    de::from_pairs,
+    error::XpressError,
+    parser::{form_decode, parse_query_pairs, percent_decode},
+    request::Request,
+    response::{IntoResponse, Response},
+};
+
+/// Types that can be built from an incoming request and used as handler
+/// arguments with [`handler`].
+///
+/// Returning an error rejects the request before the handler runs; the
+/// built-in extractors answer malformed input with `400 Bad Request`.
+pub trait FromRequest: Sized {
+    fn from_request(req: &Request) -> Result<Self, XpressError>;
+}
+
+/// The request body deserialized from JSON.
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(req: &Request) -> Result<Self, XpressError> {
+        req.from_json().map(Json)
+    }
+}
+
+/// The request body deserialized from an `application/x-www-form-urlencoded`
+/// form. See [`Request::form`].
+#[derive(Debug)]
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(req: &Request) -> Result<Self, XpressError> {
+        req.form().map(Form)
+    }
+}
+
+/// Route params such as `:id`, percent-decoded and deserialized into a
+/// struct with one field per param, or into a single value when the route
+/// has exactly one param.
+#[derive(Debug)]
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from_request(req: &Request) -> Result<Self, XpressError> {
+        let params: Vec<(&str, String)> = req
+            .params
+            .iter()
+            .map(|(k, v)| (k.as_str(), percent_decode(v)))
+            .collect();
+        from_pairs(params.iter().map(|(k, v)| (*k, v.as_str())))
+            .map(Path)
+            .map_err(|e| XpressError::ParsingError(format!("Invalid path params: {}", e)))
+    }
+}
+
+/// The query string, decoded like a form and deserialized into `T`.
+/// Repeated keys fill `Vec` fields.
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(req: &Request) -> Result<Self, XpressError> {
+        let pairs: Vec<(String, String)> = parse_query_pairs(&req.query_string)
+            .map(|(k, v)| (form_decode(k), form_decode(v)))
+            .collect();
+        from_pairs(pairs.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            .map(Query)
+            .map_err(|e| XpressError::ParsingError(format!("Invalid query string: {}", e)))
+    }
+}
+
+/// Application state registered with [`Xpress::with_state`](crate::Xpress::with_state).
+#[derive(Debug)]
+pub struct State<T>(pub Arc<T>);
+
+impl<T: Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(req: &Request) -> Result<Self, XpressError> {
+        req.state
+            .get::<Arc<T>>()
+            .map(|state| State(Arc::clone(state)))
+            .ok_or_else(|| {
+                XpressError::Custom(format!(
+                    "No state of type {} registered",
+                    std::any::type_name::<T>()
+                ))
+            })
+    }
+}
+
+/// A copy of the request headers.
+#[derive(Debug, Clone)]
+pub struct Headers(HashMap<String, String>);
+
+impl Headers {
+    /// Looks up a header by name, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+impl FromRequest for Headers {
+    fn from_request(req: &Request) -> Result<Self, XpressError> {
+        Ok(Headers(req.headers.clone()))
+    }
+}
+
+macro_rules! impl_deref {
+    ($($extractor:ident),*) => {
+        $(
+            impl<T> Deref for $extractor<T> {
+                type Target = T;
+
+                fn deref(&self) -> &T {
+                    &self.0
+                }
+            }
+        )*
+    };
+}
+
+impl_deref!(Json, Form, Path, Query);
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Functions usable as handlers, either wrapped with [`handler`] or passed to
+/// [`Xpress::route`](crate::Xpress::route).
+///
+/// Implemented for functions taking up to six [`FromRequest`] arguments that
+/// either write to a trailing `&mut Response` or return an [`IntoResponse`],
+/// and for functions taking `&Request` in the same two styles. `Args` only
+/// distinguishes the signatures and is always inferred.
+pub trait HandlerFn<Args>: Send + Sync + 'static {
+    fn call(&self, req: &Request, res: &mut Response) -> Result<(), XpressError>;
+}
+
+macro_rules! impl_handler_fn {
+    ($($ty:ident),*) => {
+        impl<F, $($ty,)*> HandlerFn<fn($($ty,)* &mut Response)> for F
+        where
+            F: Fn($($ty,)* &mut Response) -> Result<(), XpressError> + Send + Sync + 'static,
+            $($ty: FromRequest,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn call(&self, req: &Request, res: &mut Response) -> Result<(), XpressError> {
+                $(let $ty = $ty::from_request(req)?;)*
+                self($($ty,)* res)
+            }
+        }
+
+        impl<F, R, $($ty,)*> HandlerFn<fn($($ty,)*) -> R> for F
+        where
+            F: Fn($($ty,)*) -> R + Send + Sync + 'static,
+            R: IntoResponse,
+            $($ty: FromRequest,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn call(&self, req: &Request, res: &mut Response) -> Result<(), XpressError> {
+                $(let $ty = $ty::from_request(req)?;)*
+                res.merge(self($($ty,)*).into_response()?);
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_handler_fn!();
+impl_handler_fn!(A);
+impl_handler_fn!(A, B);
+impl_handler_fn!(A, B, C);
+impl_handler_fn!(A, B, C, D);
+impl_handler_fn!(A, B, C, D, E);
+impl_handler_fn!(A, B, C, D, E, G);
+
+impl<F> HandlerFn<fn(&Request, &mut Response)> for F
+where
+    F: Fn(&Request, &mut Response) -> Result<(), XpressError> + Send + Sync + 'static,
+{
+    fn call(&self, req: &Request, res: &mut Response) -> Result<(), XpressError> {
+        self(req, res)
+    }
+}
+
+impl<F, R> HandlerFn<fn(&Request) -> R> for F
+where
+    F: Fn(&Request) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    fn call(&self, req: &Request, res: &mut Response) -> Result<(), XpressError> {
+        res.merge(self(req).into_response()?);
+        Ok(())
+    }
+}
+
+/// Adapts a function taking extractors into a handler accepted by
+/// [`Xpress::get`](crate::Xpress::get) and friends.
+///
+/// ```ignore
+/// app.get("/users/:id", handler(|Path(id): Path<u32>, State(db): State<Db>, res: &mut Response| {
+///     res.json(&db.find(id)?)
+/// }));
+/// ```
+pub fn handler<Args, H>(
+    handler: H,
+) -> impl Fn(&Request, &mut Response) -> Result<(), XpressError> + Send + Sync + 'static
+where
+    H: HandlerFn<Args>,
+    Args: 'static,
+{
+    move |req, res| handler.call(req, res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct UserParams {
+        user_id: u32,
+        slug: String,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Search {
+        q: String,
+        page: Option<u32>,
+        #[serde(default)]
+        tag: Vec<String>,
+    }
+
+    fn request() -> Request {
+        let mut req = Request::default();
+        req.params.insert("user_id".into(), "42".into());
+        req.params.insert("slug".into(), "hello".into());
+        req.query_string = "q=rust&tag=a&tag=b".into();
+        req.headers
+            .insert("Content-Type".into(), "application/json".into());
+        req.body = r#"{"user_id": 1, "slug": "body"}"#.into();
+        req
+    }
+
+    #[test]
+    fn test_path_struct() {
+        let Path(params) = Path::<UserParams>::from_request(&request()).unwrap();
+        assert_eq!(
+            params,
+            UserParams {
+                user_id: 42,
+                slug: "hello".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_path_single_value() {
+        let mut req = Request::default();
+        req.params.insert("id".into(), "7".into());
+        assert_eq!(Path::<u64>::from_request(&req).unwrap().0, 7);
+    }
+
+    #[test]
+    fn test_path_is_percent_decoded() {
+        let mut req = Request::default();
+        req.params.insert("name".into(), "a%20b+c%C3%A9".into());
+        assert_eq!(Path::<String>::from_request(&req).unwrap().0, "a b+cé");
+    }
+
+    #[test]
+    fn test_path_invalid_value_is_bad_request() {
+        let mut req = Request::default();
+        req.params.insert("id".into(), "abc".into());
+        let err = Path::<u64>::from_request(&req).unwrap_err();
+        assert_eq!(err.status_code(), 400);
+    }
+
+    #[test]
+    fn test_query_with_repeated_keys() {
+        let Query(search) = Query::<Search>::from_request(&request()).unwrap();
+        assert_eq!(
+            search,
+            Search {
+                q: "rust".into(),
+                page: None,
+                tag: vec!["a".into(), "b".into()]
+            }
+        );
+    }
+
+    #[test]
+    fn test_query_is_form_decoded() {
+        let req = Request {
+            query_string: "q=a%20b+c&t%61g=%C3%A9t%C3%A9".into(),
+            ..Request::default()
+        };
+        let Query(search) = Query::<Search>::from_request(&req).unwrap();
+        assert_eq!(search.q, "a b c");
+        assert_eq!(search.tag, ["été"]);
+    }
+
+    #[test]
+    fn test_query_missing_field_is_bad_request() {
+        let err = Query::<Search>::from_request(&Request::default()).unwrap_err();
+        assert_eq!(err.status_code(), 400);
+    }
+
+    #[test]
+    fn test_json_body() {
+        let Json(body) = Json::<UserParams>::from_request(&request()).unwrap();
+        assert_eq!(body.slug, "body");
+
+        let err = Json::<UserParams>::from_request(&Request::default()).unwrap_err();
+        assert_eq!(err.status_code(), 400);
+    }
+
+    #[test]
+    fn test_headers() {
+        let headers = Headers::from_request(&request()).unwrap();
+        assert_eq!(headers.get("content-type"), Some("application/json"));
+    }
+
+    #[test]
+    fn test_handler_runs_extractors() {
+        let h = handler(
+            |Path(params): Path<UserParams>, Query(search): Query<Search>, res: &mut Response| {
+                res.send(format!("{} {} {}", params.user_id, params.slug, search.q))
+            },
+        );
+
+        let mut res = Response::new();
+        h(&request(), &mut res).unwrap();
+        assert_eq!(res.body, b"42 hello rust");
+    }
+
+    #[test]
+    fn test_handler_returning_into_response() {
+        let h = handler(|Path(params): Path<UserParams>| {
+            (201, Json(format!("created {}", params.user_id)))
+        });
+
+        let mut res = Response::new();
+        res.headers.insert("X-Trace".into(), "1".into());
+        h(&request(), &mut res).unwrap();
+        assert_eq!(res.status, 201);
+        assert_eq!(res.body, br#""created 42""#);
+        assert_eq!(res.headers["Content-Type"], "application/json");
+        assert_eq!(res.headers["X-Trace"], "1");
+    }
+
+    #[test]
+    fn test_handler_taking_request() {
+        let h = handler(|req: &Request| req.params["slug"].clone());
+
+        let mut res = Response::new();
+        h(&request(), &mut res).unwrap();
+        assert_eq!(res.body, b"hello");
+    }
+
+    #[test]
+    fn test_handler_rejects_before_running() {
+        let h = handler(
+            |_: Json<UserParams>, _: &mut Response| -> Result<(), XpressError> {
+                panic!("handler must not run")
+            },
+        );
+
+        let err = h(&Request::default(), &mut Response::new()).unwrap_err();
+        assert_eq!(err.status_code(), 400);
+    }
+}
@@ -1,9 +1,60 @@
+pub(crate) mod compression;
+mod conditional;
+pub(crate) mod cookie;
+pub(crate) mod cookie_jar;
+pub(crate) mod de;
+pub(crate) mod decompression;
 pub(crate) mod error;
+pub(crate) mod extensions;
+pub(crate) mod extract;
+pub(crate) mod method;
+pub(crate) mod middleware;
+mod mime;
+pub(crate) mod multipart;
+pub(crate) mod negotiate;
 pub(crate) mod parser;
+pub(crate) mod problem;
+mod proxy_protocol;
+mod range;
 pub(crate) mod request;
 pub(crate) mod response;
 pub(crate) mod router;
+pub(crate) mod session;
+pub(crate) mod sse;
+pub(crate) mod static_files;
+pub(crate) mod stream;
+#[cfg(feature = "template")]
+pub(crate) mod template;
+pub(crate) mod testing;
 mod thread_pool;
+#[cfg(feature = "tls")]
+pub(crate) mod tls;
+pub(crate) mod websocket;
 pub(crate) mod xpress;
-pub use error::XpressError;
+pub use compression::{compression, CompressionConfig};
+pub use cookie::{Cookie, SameSite};
+pub use cookie_jar::{CookieJar, Key, PrivateJar, SignedJar};
+pub use decompression::decompression;
+pub use error::{HttpError, XpressError};
+pub use extensions::Extensions;
+pub use extract::{handler, Form, FromRequest, HandlerFn, Headers, Json, Path, Query, State};
+pub use method::Method;
+pub use middleware::Next;
+pub use multipart::{FormData, Multipart, Part, UploadedFile};
+pub use negotiate::Format;
+pub use parser::RequestParser;
+pub use problem::{problem_json, ProblemDetails};
+pub use request::Request;
+pub use response::{IntoResponse, Response};
+pub use router::Route;
+pub use session::{
+    sessions, FileStore, MemoryStore, Session, SessionConfig, SessionData, SessionStore,
+};
+pub use sse::{Event, EventSender};
+#[cfg(feature = "template")]
+pub use template::Templates;
+pub use testing::{RequestBuilder, TestClient};
+#[cfg(feature = "tls")]
+pub use tls::{TlsConfig, TlsInfo};
+pub use websocket::{CloseFrame, Message, WebSocket};
 pub use xpress::Xpress;
@@ -0,0 +1,112 @@
+use std::{fmt, str::FromStr};
+
+use crate::XpressError;
+
+/// An HTTP request method. Methods outside the standard set are kept as
+/// [`Method::Other`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Method {
+    #[default]
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    Connect,
+    Trace,
+    Other(String),
+}
+
+impl Method {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Connect => "CONNECT",
+            Method::Trace => "TRACE",
+            Method::Other(method) => method,
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = XpressError;
+
+    /// Methods are case-sensitive, so `get` becomes `Method::Other("get")`.
+    fn from_str(method: &str) -> Result<Self, Self::Err> {
+        // RFC 9110: method = token
+        let is_token = !method.is_empty()
+            && method
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+        if !is_token {
+            return Err(XpressError::ParsingError(format!(
+                "Invalid method: {:?}",
+                method
+            )));
+        }
+
+        Ok(match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "OPTIONS" => Method::Options,
+            "CONNECT" => Method::Connect,
+            "TRACE" => Method::Trace,
+            other => Method::Other(other.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<str> for Method {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Method {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_method() {
+        assert_eq!("GET".parse::<Method>().unwrap(), Method::Get);
+        assert_eq!("PATCH".parse::<Method>().unwrap(), Method::Patch);
+        assert_eq!(
+            "PURGE".parse::<Method>().unwrap(),
+            Method::Other("PURGE".into())
+        );
+        assert!("GE T".parse::<Method>().is_err());
+        assert!("".parse::<Method>().is_err());
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for method in ["GET", "HEAD", "OPTIONS", "MKCOL"] {
+            assert_eq!(method.parse::<Method>().unwrap().to_string(), method);
+        }
+        assert_eq!(Method::Delete, "DELETE");
+    }
+}
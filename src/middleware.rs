@@ -0,0 +1,100 @@
+use crate::{error::XpressError, request::Request, response::Response};
+
+pub(crate) type Middleware =
+    Box<dyn Fn(&mut Request, &mut Response, Next<'_>) -> Result<(), XpressError> + Send + Sync>;
+
+pub(crate) type Endpoint<'a> = dyn Fn(&mut Request, &mut Response) -> Result<(), XpressError> + 'a;
+
+/// The rest of the middleware chain, ending with the route handler.
+///
+/// A middleware calls [`Next::run`] to pass the request on, and can inspect or
+/// modify the response once it returns. Not calling it short-circuits the
+/// chain with whatever the middleware wrote to the response.
+pub struct Next<'a> {
+    middleware: &'a [Middleware],
+    endpoint: &'a Endpoint<'a>,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middleware: &'a [Middleware], endpoint: &'a Endpoint<'a>) -> Self {
+        Self {
+            middleware,
+            endpoint,
+        }
+    }
+
+    pub fn run(self, req: &mut Request, res: &mut Response) -> Result<(), XpressError> {
+        match self.middleware.split_first() {
+            Some((current, rest)) => current(req, res, Next::new(rest, self.endpoint)),
+            None => (self.endpoint)(req, res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn recording(log: &Arc<Mutex<Vec<&'static str>>>, name: &'static str) -> Middleware {
+        let log = Arc::clone(log);
+        Box::new(move |req, res, next| {
+            log.lock().unwrap().push(name);
+            next.run(req, res)
+        })
+    }
+
+    #[test]
+    fn test_runs_middleware_in_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let middleware = vec![recording(&log, "first"), recording(&log, "second")];
+        let endpoint_log = Arc::clone(&log);
+        let endpoint = move |_req: &mut Request, _res: &mut Response| {
+            endpoint_log.lock().unwrap().push("handler");
+            Ok(())
+        };
+
+        Next::new(&middleware, &endpoint)
+            .run(&mut Request::default(), &mut Response::new())
+            .unwrap();
+
+        assert_eq!(*log.lock().unwrap(), vec!["first", "second", "handler"]);
+    }
+
+    #[test]
+    fn test_middleware_can_short_circuit() {
+        let middleware: Vec<Middleware> = vec![Box::new(|_req, res, _next| {
+            res.status(401);
+            Ok(())
+        })];
+        let endpoint = |_req: &mut Request, _res: &mut Response| -> Result<(), XpressError> {
+            panic!("handler must not run")
+        };
+
+        let mut res = Response::new();
+        Next::new(&middleware, &endpoint)
+            .run(&mut Request::default(), &mut res)
+            .unwrap();
+        assert_eq!(res.status, 401);
+    }
+
+    #[test]
+    fn test_middleware_passes_extensions_downstream() {
+        struct UserId(u32);
+
+        let middleware: Vec<Middleware> = vec![Box::new(|req, res, next| {
+            req.extensions_mut().insert(UserId(7));
+            next.run(req, res)
+        })];
+        let endpoint = |req: &mut Request, res: &mut Response| {
+            let id = req.extensions().get::<UserId>().unwrap().0;
+            res.send(id.to_string())
+        };
+
+        let mut res = Response::new();
+        Next::new(&middleware, &endpoint)
+            .run(&mut Request::default(), &mut res)
+            .unwrap();
+        assert_eq!(res.body, b"7");
+    }
+}
@@ -0,0 +1,646 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use derivative::Derivative;
+use serde::de::DeserializeOwned;
+
+use crate::{de::from_pairs, session::generate_id, XpressError};
+
+const CHUNK_SIZE: usize = 8 * 1024;
+const MAX_HEADERS_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Preamble,
+    InPart { read: u64 },
+    AfterDelimiter,
+    Done,
+}
+
+/// An incremental `multipart/form-data` parser (RFC 7578) over any reader.
+///
+/// Parts are read one at a time with [`Multipart::next_part`], or all at
+/// once with [`Multipart::form_data`]. Exceeding a size limit fails with
+/// `413 Content Too Large`, malformed bodies with `400 Bad Request`.
+///
+/// From [`Request::multipart`](crate::Request::multipart) it reads straight
+/// from the connection, so with files spooled to disk these limits bound the
+/// memory an upload takes.
+///
+/// ```ignore
+/// let mut multipart = req.multipart()?.max_part_size(5 * 1024 * 1024);
+/// while let Some(mut part) = multipart.next_part()? {
+///     if part.filename().is_some() {
+///         part.save_to("uploads/avatar.png")?;
+///     }
+/// }
+/// ```
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+pub struct Multipart<R> {
+    #[derivative(Debug = "ignore")]
+    reader: R,
+    buffer: Vec<u8>,
+    delimiter: Vec<u8>,
+    state: State,
+    total_read: u64,
+    max_part_size: u64,
+    max_total_size: u64,
+    spool_dir: Option<PathBuf>,
+}
+
+impl<R: Read> Multipart<R> {
+    /// Parses `reader` using the boundary from the `Content-Type` header.
+    /// Defaults to 10 MiB per part and 50 MiB in total.
+    pub fn new(reader: R, boundary: &str) -> Self {
+        Self {
+            reader,
+            // The delimiter includes the CRLF ending the previous part, so
+            // one is prepended for the first boundary.
+            buffer: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            state: State::Preamble,
+            total_read: 0,
+            max_part_size: 10 * 1024 * 1024,
+            max_total_size: 50 * 1024 * 1024,
+            spool_dir: None,
+        }
+    }
+
+    pub fn max_part_size(mut self, bytes: u64) -> Self {
+        self.max_part_size = bytes;
+        self
+    }
+
+    pub fn max_total_size(mut self, bytes: u64) -> Self {
+        self.max_total_size = bytes;
+        self
+    }
+
+    /// Makes [`Multipart::form_data`] write file parts to uniquely named
+    /// files in `dir` instead of keeping them in memory.
+    pub fn spool_files_to(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.spool_dir = Some(dir.into());
+        self
+    }
+
+    /// Advances to the next part, skipping whatever is left of the current
+    /// one. Returns `None` after the closing boundary.
+    pub fn next_part(&mut self) -> Result<Option<Part<'_, R>>, XpressError> {
+        loop {
+            match self.state {
+                State::Done => return Ok(None),
+                State::InPart { .. } => {
+                    let mut sink = [0; CHUNK_SIZE];
+                    while self.read_part(&mut sink)? > 0 {}
+                }
+                State::Preamble => match find(&self.buffer, &self.delimiter) {
+                    Some(i) => {
+                        self.buffer.drain(..i + self.delimiter.len());
+                        self.state = State::AfterDelimiter;
+                    }
+                    None => {
+                        let keep = self.delimiter.len() - 1;
+                        let skip = self.buffer.len().saturating_sub(keep);
+                        self.buffer.drain(..skip);
+                        if !self.fill()? {
+                            return Err(XpressError::bad_request("Multipart boundary not found"));
+                        }
+                    }
+                },
+                State::AfterDelimiter => return self.start_part(),
+            }
+        }
+    }
+
+    /// Reads every part, keeping fields in memory and file parts in memory
+    /// or in the spool directory.
+    pub fn form_data(mut self) -> Result<FormData, XpressError> {
+        let spool_dir = self.spool_dir.take();
+        let mut form = FormData::default();
+
+        while let Some(mut part) = self.next_part()? {
+            let name = part.name().to_string();
+            let Some(filename) = part.filename().map(str::to_string) else {
+                let value = String::from_utf8(part.read_all()?).map_err(|_| {
+                    XpressError::bad_request(format!("Field {:?} is not valid UTF-8", name))
+                })?;
+                form.fields.push((name, value));
+                continue;
+            };
+
+            let content_type = part.content_type().map(str::to_string);
+            let (contents, size) = match &spool_dir {
+                Some(dir) => {
+                    // Random names, as client-supplied filenames are untrusted
+                    let path = dir.join(format!("upload-{}", generate_id()));
+                    let size = part.save_to(&path)?;
+                    (Contents::Disk(path), size)
+                }
+                None => {
+                    let bytes = part.read_all()?;
+                    let size = bytes.len() as u64;
+                    (Contents::Memory(bytes), size)
+                }
+            };
+            form.files.push(UploadedFile {
+                name,
+                filename,
+                content_type,
+                size,
+                contents,
+            });
+        }
+
+        Ok(form)
+    }
+
+    // Reads more input, enforcing the total size limit. Returns `false` at
+    // the end of the input.
+    fn fill(&mut self) -> Result<bool, XpressError> {
+        let mut chunk = [0; CHUNK_SIZE];
+        let read = self.reader.read(&mut chunk)?;
+        self.total_read += read as u64;
+        if self.total_read > self.max_total_size {
+            return Err(XpressError::payload_too_large());
+        }
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(read > 0)
+    }
+
+    fn start_part(&mut self) -> Result<Option<Part<'_, R>>, XpressError> {
+        while self.buffer.len() < 2 {
+            if !self.fill()? {
+                return Err(unexpected_end());
+            }
+        }
+        if self.buffer.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None);
+        }
+
+        let headers_end = loop {
+            if let Some(i) = find(&self.buffer, b"\r\n\r\n") {
+                break i;
+            }
+            if self.buffer.len() > MAX_HEADERS_SIZE {
+                return Err(XpressError::bad_request("Multipart headers too large"));
+            }
+            if !self.fill()? {
+                return Err(unexpected_end());
+            }
+        };
+
+        let raw = std::str::from_utf8(&self.buffer[..headers_end])
+            .map_err(|_| XpressError::bad_request("Invalid multipart headers"))?;
+        let mut lines = raw.split("\r\n");
+        // The rest of the boundary line may only hold whitespace
+        if !lines.next().unwrap_or("").trim().is_empty() {
+            return Err(XpressError::bad_request("Malformed multipart boundary"));
+        }
+        let headers = lines
+            .map(|line| {
+                line.split_once(':')
+                    .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                    .ok_or_else(|| XpressError::bad_request("Malformed multipart header"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.buffer.drain(..headers_end + 4);
+
+        let disposition = find_header(&headers, "Content-Disposition")
+            .ok_or_else(|| XpressError::bad_request("Part without Content-Disposition"))?;
+        let params = parse_params(disposition);
+        let name = find_header(&params, "name")
+            .ok_or_else(|| XpressError::bad_request("Part without a name"))?
+            .to_string();
+        let filename = find_header(&params, "filename").map(str::to_string);
+
+        self.state = State::InPart { read: 0 };
+        Ok(Some(Part {
+            multipart: self,
+            name,
+            filename,
+            headers,
+        }))
+    }
+
+    fn read_part(&mut self, out: &mut [u8]) -> Result<usize, XpressError> {
+        let State::InPart { read } = self.state else {
+            return Ok(0);
+        };
+
+        let available = loop {
+            if let Some(i) = find(&self.buffer, &self.delimiter) {
+                if i == 0 {
+                    self.buffer.drain(..self.delimiter.len());
+                    self.state = State::AfterDelimiter;
+                    return Ok(0);
+                }
+                break i;
+            }
+            // Hold back bytes that may be the start of a split delimiter
+            let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+            if safe > 0 {
+                break safe;
+            }
+            if !self.fill()? {
+                return Err(unexpected_end());
+            }
+        };
+
+        let n = available.min(out.len());
+        out[..n].copy_from_slice(&self.buffer[..n]);
+        self.buffer.drain(..n);
+
+        let read = read + n as u64;
+        if read > self.max_part_size {
+            return Err(XpressError::payload_too_large());
+        }
+        self.state = State::InPart { read };
+        Ok(n)
+    }
+}
+
+/// One part of a multipart body. Reading it yields the part's content.
+pub struct Part<'a, R> {
+    multipart: &'a mut Multipart<R>,
+    name: String,
+    filename: Option<String>,
+    headers: Vec<(String, String)>,
+}
+
+impl<R: Read> Part<'_, R> {
+    /// The form field name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The client-supplied filename of a file upload. Never use it as a
+    /// path without sanitizing it.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("Content-Type")
+    }
+
+    /// Looks up a part header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Reads the rest of the part into memory.
+    pub fn read_all(&mut self) -> Result<Vec<u8>, XpressError> {
+        let mut bytes = Vec::new();
+        self.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Streams the rest of the part into a new file at `path`, returning its
+    /// size. The file is removed if the part cannot be read completely.
+    pub fn save_to(&mut self, path: impl AsRef<Path>) -> Result<u64, XpressError> {
+        let path = path.as_ref();
+        let result = File::create(path).and_then(|mut file| {
+            let size = io::copy(self, &mut file)?;
+            file.flush()?;
+            Ok(size)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(path);
+        }
+        Ok(result?)
+    }
+}
+
+impl<R: Read> Read for Part<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.multipart.read_part(buf).map_err(io::Error::other)
+    }
+}
+
+/// All parts of a multipart body, read with [`Multipart::form_data`].
+#[derive(Debug, Default)]
+pub struct FormData {
+    fields: Vec<(String, String)>,
+    files: Vec<UploadedFile>,
+}
+
+impl FormData {
+    /// The first value of a text field.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        find_header(&self.fields, name)
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Deserializes the text fields, like [`Request::form`](crate::Request::form).
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, XpressError> {
+        from_pairs(self.fields())
+            .map_err(|e| XpressError::bad_request(format!("Invalid form data: {}", e)))
+    }
+
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    pub fn files(&self) -> &[UploadedFile] {
+        &self.files
+    }
+
+    pub fn into_files(self) -> Vec<UploadedFile> {
+        self.files
+    }
+}
+
+#[derive(Debug)]
+enum Contents {
+    Memory(Vec<u8>),
+    Disk(PathBuf),
+    Persisted,
+}
+
+/// A file part of a multipart body. Spooled files are deleted when this is
+/// dropped unless moved with [`UploadedFile::persist`].
+#[derive(Debug)]
+pub struct UploadedFile {
+    name: String,
+    filename: String,
+    content_type: Option<String>,
+    size: u64,
+    contents: Contents,
+}
+
+impl UploadedFile {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The client-supplied filename. Never use it as a path without
+    /// sanitizing it.
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Location of the spooled file, or `None` for files kept in memory.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.contents {
+            Contents::Disk(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(&self) -> Result<Vec<u8>, XpressError> {
+        match &self.contents {
+            Contents::Memory(bytes) => Ok(bytes.clone()),
+            Contents::Disk(path) => Ok(fs::read(path)?),
+            Contents::Persisted => unreachable!("persist consumes the file"),
+        }
+    }
+
+    /// Moves the file to `path`.
+    pub fn persist(mut self, path: impl AsRef<Path>) -> Result<(), XpressError> {
+        let path = path.as_ref();
+        match std::mem::replace(&mut self.contents, Contents::Persisted) {
+            Contents::Memory(bytes) => fs::write(path, bytes)?,
+            // Renaming fails across filesystems, so fall back to copying
+            Contents::Disk(spooled) => {
+                if fs::rename(&spooled, path).is_err() {
+                    let copied = fs::copy(&spooled, path);
+                    let _ = fs::remove_file(&spooled);
+                    copied?;
+                }
+            }
+            Contents::Persisted => unreachable!("persist consumes the file"),
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if let Contents::Disk(path) = &self.contents {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Extracts the boundary from a `multipart/form-data` content type.
+pub(crate) fn boundary(content_type: &str) -> Option<String> {
+    let (media_type, params) = content_type.split_once(';')?;
+    if !media_type
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    find_header(&parse_params(params), "boundary")
+        .filter(|boundary| (1..=70).contains(&boundary.len()))
+        .map(str::to_string)
+}
+
+// Parses `key=value` parameters separated by `;`, with optionally quoted
+// values. Tokens without `=`, such as `form-data`, are skipped.
+fn parse_params(header: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = header;
+    while !rest.is_empty() {
+        let (key, after_key) = match rest.find(['=', ';']) {
+            Some(i) if rest.as_bytes()[i] == b'=' => (&rest[..i], &rest[i + 1..]),
+            Some(i) => {
+                rest = &rest[i + 1..];
+                continue;
+            }
+            None => break,
+        };
+
+        let after_key = after_key.trim_start();
+        let (value, remaining) = if let Some(quoted) = after_key.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            let remaining = &quoted[end..];
+            (
+                value,
+                remaining.find(';').map_or("", |i| &remaining[i + 1..]),
+            )
+        } else {
+            match after_key.split_once(';') {
+                Some((value, remaining)) => (value.trim().to_string(), remaining),
+                None => (after_key.trim().to_string(), ""),
+            }
+        };
+
+        params.push((key.trim().to_ascii_lowercase(), value));
+        rest = remaining;
+    }
+    params
+}
+
+fn find_header<'a>(pairs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    pairs
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn unexpected_end() -> XpressError {
+    XpressError::bad_request("Unexpected end of multipart body")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Holiday\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"photo\"; filename=\"a \\\"b\\\";.png\"\r\n\
+        Content-Type: image/png\r\n\
+        \r\n\
+        \x89PNG\r\n--Xy\r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    // Hands out at most `step` bytes per read to exercise split boundaries
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_boundary_from_content_type() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"a b\"").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(boundary("multipart/form-data").as_deref(), None);
+        assert_eq!(boundary("text/plain; boundary=x").as_deref(), None);
+    }
+
+    #[test]
+    fn test_parts_in_small_reads() {
+        for step in [1, 3, 7, 64, CHUNK_SIZE] {
+            let mut multipart = Multipart::new(Trickle { data: BODY, step }, "XyZ");
+
+            let mut part = multipart.next_part().unwrap().unwrap();
+            assert_eq!(part.name(), "title");
+            assert_eq!(part.filename(), None);
+            assert_eq!(part.read_all().unwrap(), b"Holiday");
+
+            let mut part = multipart.next_part().unwrap().unwrap();
+            assert_eq!(part.name(), "photo");
+            assert_eq!(part.filename(), Some("a \"b\";.png"));
+            assert_eq!(part.content_type(), Some("image/png"));
+            assert_eq!(part.read_all().unwrap(), b"\x89PNG\r\n--Xy");
+
+            assert!(multipart.next_part().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_unread_parts_are_skipped() {
+        let mut multipart = Multipart::new(BODY, "XyZ");
+        assert_eq!(multipart.next_part().unwrap().unwrap().name(), "title");
+        assert_eq!(multipart.next_part().unwrap().unwrap().name(), "photo");
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut multipart = Multipart::new(BODY, "XyZ").max_part_size(4);
+        let err = multipart
+            .next_part()
+            .unwrap()
+            .unwrap()
+            .read_all()
+            .unwrap_err();
+        assert_eq!(err.status_code(), 413);
+
+        let err = Multipart::new(BODY, "XyZ")
+            .max_total_size(32)
+            .form_data()
+            .unwrap_err();
+        assert_eq!(err.status_code(), 413);
+    }
+
+    #[test]
+    fn test_truncated_body() {
+        let err = Multipart::new(&BODY[..60], "XyZ").form_data().unwrap_err();
+        assert_eq!(err.status_code(), 400);
+        let err = Multipart::new(&b"no boundary here"[..], "XyZ")
+            .form_data()
+            .unwrap_err();
+        assert_eq!(err.status_code(), 400);
+    }
+
+    #[test]
+    fn test_form_data_spools_files() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let form = Multipart::new(BODY, "XyZ")
+            .spool_files_to(dir.path())
+            .form_data()
+            .unwrap();
+        assert_eq!(form.field("title"), Some("Holiday"));
+
+        let file = form.file("photo").unwrap();
+        let spooled = file.path().unwrap().to_path_buf();
+        assert_eq!(fs::read(&spooled).unwrap(), b"\x89PNG\r\n--Xy");
+        assert_eq!(file.size(), 10);
+
+        let target = dir.path().join("kept.png");
+        form.into_files().pop().unwrap().persist(&target).unwrap();
+        assert!(!spooled.exists());
+        assert!(target.exists());
+
+        let form = Multipart::new(BODY, "XyZ")
+            .spool_files_to(dir.path())
+            .form_data()
+            .unwrap();
+        let spooled = form.file("photo").unwrap().path().unwrap().to_path_buf();
+        drop(form);
+        assert!(!spooled.exists());
+    }
+}
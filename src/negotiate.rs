@@ -0,0 +1,191 @@
+use crate::{request::Request, response::Response, XpressError};
+
+type Renderer<'a> = Box<dyn FnOnce(&mut Response) -> Result<(), XpressError> + 'a>;
+
+/// Picks the offered media type the `Accept` header ranks highest. Ties go
+/// to the earlier offer, and every offer is acceptable without a header.
+pub(crate) fn preferred<'o>(accept: Option<&str>, offers: &[&'o str]) -> Option<&'o str> {
+    let ranges: Vec<MediaRange> = match accept.map(str::trim) {
+        Some(accept) if !accept.is_empty() => {
+            accept.split(',').filter_map(MediaRange::parse).collect()
+        }
+        _ => return offers.first().copied(),
+    };
+
+    let mut best: Option<(&str, f32)> = None;
+    for offer in offers {
+        let Some((kind, subtype)) = offer.split_once('/') else {
+            continue;
+        };
+        // The most specific range matching the offer decides its quality
+        let q = ranges
+            .iter()
+            .filter_map(|range| {
+                range
+                    .specificity(kind.trim(), subtype.trim())
+                    .map(|s| (s, range.q))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0.0, |(_, q)| q);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((offer, q));
+        }
+    }
+    best.map(|(offer, _)| offer)
+}
+
+struct MediaRange {
+    kind: String,
+    subtype: String,
+    q: f32,
+}
+
+impl MediaRange {
+    fn parse(item: &str) -> Option<Self> {
+        let mut params = item.split(';');
+        let (kind, subtype) = params.next()?.trim().split_once('/')?;
+        let mut q = 1.0;
+        for param in params {
+            if let Some((key, value)) = param.split_once('=') {
+                if key.trim().eq_ignore_ascii_case("q") {
+                    q = value.trim().parse().ok()?;
+                }
+            }
+        }
+        Some(Self {
+            kind: kind.trim().to_ascii_lowercase(),
+            subtype: subtype.trim().to_ascii_lowercase(),
+            q,
+        })
+    }
+
+    // How closely the range matches a type: 2 for exact, 1 for `type/*`,
+    // 0 for `*/*`, `None` for no match.
+    fn specificity(&self, kind: &str, subtype: &str) -> Option<u8> {
+        if self.kind == "*" {
+            return Some(0);
+        }
+        if !self.kind.eq_ignore_ascii_case(kind) {
+            return None;
+        }
+        if self.subtype == "*" {
+            return Some(1);
+        }
+        self.subtype.eq_ignore_ascii_case(subtype).then_some(2)
+    }
+}
+
+/// Renders a response in whichever media type the client prefers, created
+/// with [`Response::format`].
+///
+/// ```ignore
+/// res.format(req)
+///     .on("application/json", |res| res.json(&user))
+///     .on("text/html", |res| res.send(format!("<h1>{}</h1>", user.name)))
+///     .run()
+/// ```
+pub struct Format<'a> {
+    req: &'a Request,
+    res: &'a mut Response,
+    renderers: Vec<(&'a str, Renderer<'a>)>,
+}
+
+impl<'a> Format<'a> {
+    pub(crate) fn new(req: &'a Request, res: &'a mut Response) -> Self {
+        Self {
+            req,
+            res,
+            renderers: Vec::new(),
+        }
+    }
+
+    /// Adds a renderer for a media type. Earlier ones win ties.
+    pub fn on<F>(mut self, media_type: &'a str, render: F) -> Self
+    where
+        F: FnOnce(&mut Response) -> Result<(), XpressError> + 'a,
+    {
+        self.renderers.push((media_type, Box::new(render)));
+        self
+    }
+
+    /// Runs the renderer for the preferred media type with `Content-Type`
+    /// set to it, or fails with `406 Not Acceptable`.
+    pub fn run(self) -> Result<(), XpressError> {
+        self.res.vary("Accept");
+        let offers: Vec<&str> = self.renderers.iter().map(|(offer, _)| *offer).collect();
+        let Some(chosen) = self.req.accepts(&offers) else {
+            return Err(XpressError::http(406, "Not Acceptable")
+                .with_details(serde_json::json!({ "available": offers })));
+        };
+
+        let (media_type, render) = self
+            .renderers
+            .into_iter()
+            .find(|(offer, _)| *offer == chosen)
+            .expect("the chosen type is one of the offers");
+        self.res
+            .headers
+            .insert("Content-Type".to_string(), media_type.to_string());
+        render(self.res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RequestBuilder, TestClient, Xpress};
+
+    const OFFERS: [&str; 2] = ["application/json", "text/html"];
+
+    #[test]
+    fn test_preferred() {
+        assert_eq!(preferred(None, &OFFERS), Some("application/json"));
+        assert_eq!(preferred(Some("text/html"), &OFFERS), Some("text/html"));
+        assert_eq!(
+            preferred(Some("text/html;q=0.9, application/json"), &OFFERS),
+            Some("application/json")
+        );
+        assert_eq!(
+            preferred(Some("text/*, application/json;q=0.5"), &OFFERS),
+            Some("text/html")
+        );
+        // The exact range overrides the wildcard
+        assert_eq!(
+            preferred(Some("*/*, application/json;q=0"), &OFFERS),
+            Some("text/html")
+        );
+        assert_eq!(preferred(Some("*/*"), &OFFERS), Some("application/json"));
+        assert_eq!(preferred(Some("image/png"), &OFFERS), None);
+    }
+
+    fn client() -> TestClient {
+        let mut app = Xpress::new("127.0.0.1:0");
+        app.get("/user", |req, res| {
+            res.format(req)
+                .on("application/json", |res| res.json(&"ada"))
+                .on("text/html", |res| res.send("<b>ada</b>"))
+                .run()
+        });
+        TestClient::new(app)
+    }
+
+    fn get(client: &TestClient, accept: &str) -> Response {
+        client.send(RequestBuilder::new("GET", "/user").header("Accept", accept))
+    }
+
+    #[test]
+    fn test_format() {
+        let client = client();
+
+        let res = get(&client, "text/html,application/xhtml+xml,*/*;q=0.8");
+        assert_eq!(res.body, b"<b>ada</b>");
+        assert_eq!(res.headers["Content-Type"], "text/html");
+        assert_eq!(res.headers["Vary"], "Accept");
+
+        let res = get(&client, "application/json");
+        assert_eq!(res.body, b"\"ada\"");
+
+        let res = get(&client, "image/*");
+        assert_eq!(res.status, 406);
+    }
+}
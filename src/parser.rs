@@ -1,25 +1,339 @@
-use crate::router::Segment;
-use std::collections::HashMap;
+use crate::{
+    request::{Request, DEFAULT_MAX_BODY_SIZE, MAX_HEAD_SIZE},
+    router::Segment,
+    XpressError,
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+};
+
+/// Incremental HTTP/1.x request parser for data that arrives in pieces, e.g.
+/// from a non-blocking socket or a custom transport.
+///
+/// ```ignore
+/// let mut parser = RequestParser::new();
+/// assert!(parser.feed(b"GET / HTTP/1.1\r\nHo")?.is_none());
+/// let req = parser.feed(b"st: example.com\r\n\r\n")?.unwrap();
+/// ```
+#[derive(Debug)]
+pub struct RequestParser {
+    buffer: Vec<u8>,
+    head: Option<Request>,
+    max_body_size: usize,
+}
+
+impl Default for RequestParser {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::new(),
+            head: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+impl RequestParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rejects requests whose `Content-Length` exceeds `bytes` with
+    /// `413 Content Too Large`, before buffering the body. Defaults to
+    /// 50 MiB.
+    pub fn max_body_size(mut self, bytes: usize) -> Self {
+        self.max_body_size = bytes;
+        self
+    }
+
+    /// Buffers `bytes` and returns the next request once it has fully
+    /// arrived. Bytes past the end of that request are kept, so pipelined
+    /// requests are returned by later calls, e.g. `feed(&[])`.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Option<Request>, XpressError> {
+        self.buffer.extend_from_slice(bytes);
+
+        if self.head.is_none() {
+            // Blank lines before the request line are ignored
+            let blank = self
+                .buffer
+                .iter()
+                .take_while(|b| matches!(b, b'\r' | b'\n'))
+                .count();
+            self.buffer.drain(..blank);
+
+            let end = find_head_end(&self.buffer);
+            if end.unwrap_or(self.buffer.len()) > MAX_HEAD_SIZE {
+                return Err(XpressError::ParsingError("Request head too large".into()));
+            }
+            let Some(end) = end else {
+                return Ok(None);
+            };
+            let head = std::str::from_utf8(&self.buffer[..end])
+                .map_err(|_| XpressError::ParsingError("Invalid UTF-8 in request head".into()))?;
+            let head = Request::parse_head(head)?;
+            if head.content_length()? > self.max_body_size {
+                return Err(XpressError::payload_too_large());
+            }
+            self.head = Some(head);
+            self.buffer.drain(..end);
+        }
+
+        let length = match &self.head {
+            Some(head) => head.content_length()?,
+            None => return Ok(None),
+        };
+        if self.buffer.len() < length {
+            return Ok(None);
+        }
+
+        let mut request = self.head.take().expect("head parsed above");
+        request.body = self.buffer.drain(..length).collect();
+        Ok(Some(request))
+    }
+
+    /// Bytes received but not yet part of a returned request.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+// Index just past the blank line ending the head, accepting bare `\n` line
+// endings as well as `\r\n`.
+fn find_head_end(buffer: &[u8]) -> Option<usize> {
+    buffer
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'\n')
+        .find_map(|(i, _)| match &buffer[i + 1..] {
+            [b'\n', ..] => Some(i + 2),
+            [b'\r', b'\n', ..] => Some(i + 3),
+            _ => None,
+        })
+}
 
 pub(crate) fn parse_query(query: &str) -> HashMap<String, String> {
-    query
-        .split('&')
-        .filter_map(|pair| {
-            let (k, v) = pair.split_once('=')?;
-            Some((k.to_string(), v.to_string()))
+    parse_query_pairs(query)
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// Splits a query string into its key/value pairs, keeping repeated keys.
+pub(crate) fn parse_query_pairs(query: &str) -> impl Iterator<Item = (&str, &str)> {
+    query.split('&').filter_map(|pair| pair.split_once('='))
+}
+
+/// Splits an `application/x-www-form-urlencoded` body into decoded
+/// key/value pairs, keeping repeated keys. A key without `=` gets an empty
+/// value.
+pub(crate) fn parse_form_pairs(body: &str) -> Vec<(String, String)> {
+    body.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (form_decode(key), form_decode(value))
         })
         .collect()
 }
 
+/// Decodes `+` as a space and `%XX` escapes. Malformed escapes are kept as
+/// they are, and invalid UTF-8 is replaced.
+pub(crate) fn form_decode(input: &str) -> String {
+    decode(input, true)
+}
+
+/// Decodes `%XX` escapes in a URL path segment, where `+` is literal.
+pub(crate) fn percent_decode(input: &str) -> String {
+    decode(input, false)
+}
+
+/// Escapes everything but unreserved characters (RFC 3986), making `input`
+/// safe as a path segment or query component.
+pub(crate) fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match hex {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
 pub(crate) fn parse_path_segments(path: &str) -> Vec<Segment> {
     path.split('/')
         .filter(|s| !s.is_empty())
         .map(|s| {
-            if s.starts_with(':') {
-                Segment::Dynamic(s[1..].to_string())
+            if let Some(name) = s.strip_prefix(':') {
+                Segment::Dynamic(name.to_string())
             } else {
                 Segment::Static(s.to_string())
             }
         })
         .collect()
 }
+
+/// Extracts the `for=` addresses of a `Forwarded` header (RFC 7239), in
+/// order. Obfuscated identifiers and `unknown` are skipped.
+pub(crate) fn parse_forwarded_for(header: &str) -> Vec<IpAddr> {
+    header
+        .split(',')
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                if !key.eq_ignore_ascii_case("for") {
+                    return None;
+                }
+                parse_node(value.trim().trim_matches('"'))
+            })
+        })
+        .collect()
+}
+
+/// Extracts the addresses listed in an `X-Forwarded-For` header, in order.
+pub(crate) fn parse_x_forwarded_for(header: &str) -> Vec<IpAddr> {
+    header
+        .split(',')
+        .filter_map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+// Accepts `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1` and `[2001:db8::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .and_then(|ip| ip.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::method::Method;
+
+    #[test]
+    fn test_form_decode() {
+        assert_eq!(form_decode("a+b%20c%2B%C3%A9"), "a b c+é");
+        assert_eq!(form_decode("100%"), "100%");
+        assert_eq!(form_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("a+b%2Fc"), "a+b/c");
+        assert_eq!(percent_encode("a b/ü~"), "a%20b%2F%C3%BC~");
+    }
+
+    #[test]
+    fn test_parse_form_pairs() {
+        assert_eq!(
+            parse_form_pairs("a=1&&tag=x&tag=y&flag&e%3D=%26"),
+            [
+                ("a".to_string(), "1".to_string()),
+                ("tag".to_string(), "x".to_string()),
+                ("tag".to_string(), "y".to_string()),
+                ("flag".to_string(), String::new()),
+                ("e=".to_string(), "&".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_feed_in_pieces() {
+        let raw = b"POST /items HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody";
+        let mut parser = RequestParser::new();
+        for chunk in raw[..raw.len() - 1].chunks(5) {
+            assert!(parser.feed(chunk).unwrap().is_none());
+        }
+
+        let req = parser.feed(&raw[raw.len() - 1..]).unwrap().unwrap();
+        assert_eq!(req.method(), &Method::Post);
+        assert_eq!(req.path(), "/items");
+        assert_eq!(req.body(), b"body");
+        assert!(parser.buffered().is_empty());
+    }
+
+    #[test]
+    fn test_feed_pipelined_requests() {
+        let mut parser = RequestParser::new();
+        let first = parser
+            .feed(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\n\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.path(), "/a");
+        assert_eq!(parser.feed(&[]).unwrap().unwrap().path(), "/b");
+        assert!(parser.feed(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_feed_rejects_malformed_head() {
+        let mut parser = RequestParser::new();
+        assert!(parser.feed(b"GET\r\n\r\n").is_err());
+        assert!(RequestParser::new()
+            .feed(b"GET / HTTP/1.1\r\nno-colon\r\n\r\n")
+            .is_err());
+        assert!(RequestParser::new()
+            .feed(&vec![b'a'; MAX_HEAD_SIZE + 1])
+            .is_err());
+    }
+
+    #[test]
+    fn test_feed_rejects_invalid_target() {
+        for head in [
+            &b"GET ?a=1 HTTP/1.1\r\n\r\n"[..],
+            b"GET users HTTP/1.1\r\n\r\n",
+        ] {
+            let err = RequestParser::new().feed(head).unwrap_err();
+            assert_eq!(err.status_code(), 400);
+        }
+        let req = RequestParser::new()
+            .feed(b"OPTIONS * HTTP/1.1\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.path(), "*");
+    }
+
+    #[test]
+    fn test_feed_rejects_large_body() {
+        let mut parser = RequestParser::new().max_body_size(4);
+        let err = parser
+            .feed(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n")
+            .unwrap_err();
+        assert_eq!(err.status_code(), 413);
+
+        let mut parser = RequestParser::new().max_body_size(4);
+        let req = parser
+            .feed(b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody")
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.body(), b"body");
+    }
+}
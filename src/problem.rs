@@ -0,0 +1,158 @@
+use serde::Serialize;
+
+use crate::{
+    error::XpressError,
+    request::Request,
+    response::{reason_phrase, IntoResponse, Response},
+};
+
+/// An RFC 7807 problem details object, sent as `application/problem+json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Extension member with structured data about the problem, such as
+    /// validation failures.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl ProblemDetails {
+    pub fn new(status: u16) -> Self {
+        Self {
+            type_uri: "about:blank".to_string(),
+            title: reason_phrase(status).to_string(),
+            status,
+            detail: None,
+            instance: None,
+            details: None,
+        }
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn instance(mut self, instance: impl Into<String>) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    pub fn details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(self) -> Result<Response, XpressError> {
+        let mut res = Response::new();
+        res.json(&self)?;
+        res.status = self.status;
+        res.headers.insert(
+            "Content-Type".to_string(),
+            "application/problem+json".to_string(),
+        );
+        Ok(res)
+    }
+}
+
+/// Error handler rendering every error as problem details. Register it with
+/// `app.on_error(problem_json)`.
+///
+/// The error message becomes the `detail` member and details attached with
+/// [`XpressError::with_details`] become the `details` member, except for 5xx
+/// errors, where both may leak internals and are left out.
+pub fn problem_json(
+    err: &XpressError,
+    req: &Request,
+    res: &mut Response,
+) -> Result<(), XpressError> {
+    let status = err.status_code();
+    let mut problem = ProblemDetails::new(status).instance(req.path.clone());
+    if status < 500 {
+        problem = problem.detail(err.to_string());
+        if let Some(details) = err.details() {
+            problem = problem.details(details.clone());
+        }
+    }
+
+    res.merge(problem.into_response()?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_problem_json_for_client_error() {
+        let req = Request {
+            path: "/users/9".to_string(),
+            ..Request::default()
+        };
+        let mut res = Response::new();
+        problem_json(
+            &XpressError::NotFound("GET /users/9".into()),
+            &req,
+            &mut res,
+        )
+        .unwrap();
+
+        assert_eq!(res.status, 404);
+        assert_eq!(res.headers["Content-Type"], "application/problem+json");
+        let body: serde_json::Value = serde_json::from_slice(&res.body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "detail": "Route Not Found: GET /users/9",
+                "instance": "/users/9",
+            })
+        );
+    }
+
+    #[test]
+    fn test_problem_json_redacts_server_errors() {
+        let mut res = Response::new();
+        let err = XpressError::Custom("db password is hunter2".into());
+        problem_json(&err, &Request::default(), &mut res).unwrap();
+
+        assert_eq!(res.status, 500);
+        let body: serde_json::Value = serde_json::from_slice(&res.body).unwrap();
+        assert_eq!(body["title"], "Internal Server Error");
+        assert!(body.get("detail").is_none());
+
+        let mut res = Response::new();
+        let err = XpressError::internal(XpressError::Custom("query failed".into()))
+            .with_details(serde_json::json!({"query": "SELECT * FROM users"}));
+        problem_json(&err, &Request::default(), &mut res).unwrap();
+
+        assert_eq!(res.status, 500);
+        let body: serde_json::Value = serde_json::from_slice(&res.body).unwrap();
+        assert!(body.get("detail").is_none());
+        assert!(body.get("details").is_none());
+    }
+
+    #[test]
+    fn test_problem_json_includes_details() {
+        let mut res = Response::new();
+        let err = XpressError::unprocessable("Validation failed")
+            .with_details(serde_json::json!({"email": "is invalid"}));
+        problem_json(&err, &Request::default(), &mut res).unwrap();
+
+        assert_eq!(res.status, 422);
+        let body: serde_json::Value = serde_json::from_slice(&res.body).unwrap();
+        assert_eq!(body["detail"], "Validation failed");
+        assert_eq!(body["details"]["email"], "is invalid");
+    }
+}
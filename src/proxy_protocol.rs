@@ -0,0 +1,260 @@
+use std::{
+    io::Read,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use crate::error::XpressError;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Addresses announced by a PROXY protocol header. Both are `None` when the
+/// proxy reports an unknown or local connection, e.g. its own health checks.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ProxyHeader {
+    pub(crate) source: Option<SocketAddr>,
+    pub(crate) destination: Option<SocketAddr>,
+}
+
+impl ProxyHeader {
+    fn unknown() -> Self {
+        Self {
+            source: None,
+            destination: None,
+        }
+    }
+}
+
+/// Reads a v1 or v2 PROXY protocol header without consuming anything past it,
+/// so the stream can be handed to TLS or the request parser afterwards.
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> Result<ProxyHeader, XpressError> {
+    let mut prefix = [0; 6];
+    reader.read_exact(&mut prefix)?;
+
+    if prefix == V1_PREFIX {
+        read_v1(reader)
+    } else if prefix == V2_SIGNATURE[..6] {
+        read_v2(reader)
+    } else {
+        Err(invalid("missing PROXY protocol signature"))
+    }
+}
+
+fn read_v1<R: Read>(reader: &mut R) -> Result<ProxyHeader, XpressError> {
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+    while !line.ends_with(b"\r\n") {
+        if line.len() + V1_PREFIX.len() >= V1_MAX_LEN {
+            return Err(invalid("v1 header too long"));
+        }
+        reader.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    line.truncate(line.len() - 2);
+
+    let line = std::str::from_utf8(&line).map_err(|_| invalid("v1 header is not ASCII"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.as_slice() {
+        ["UNKNOWN", ..] => Ok(ProxyHeader::unknown()),
+        [family @ ("TCP4" | "TCP6"), src, dst, src_port, dst_port] => {
+            let parse_ip = |ip: &str| -> Result<IpAddr, XpressError> {
+                let ip = ip
+                    .parse::<IpAddr>()
+                    .map_err(|_| invalid("invalid v1 address"))?;
+                if ip.is_ipv4() != (*family == "TCP4") {
+                    return Err(invalid("v1 address does not match family"));
+                }
+                Ok(ip)
+            };
+            let parse_port = |port: &str| -> Result<u16, XpressError> {
+                if port.len() > 1 && port.starts_with('0') {
+                    return Err(invalid("invalid v1 port"));
+                }
+                port.parse().map_err(|_| invalid("invalid v1 port"))
+            };
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(parse_ip(src)?, parse_port(src_port)?)),
+                destination: Some(SocketAddr::new(parse_ip(dst)?, parse_port(dst_port)?)),
+            })
+        }
+        _ => Err(invalid("malformed v1 header")),
+    }
+}
+
+fn read_v2<R: Read>(reader: &mut R) -> Result<ProxyHeader, XpressError> {
+    let mut rest = [0; 10];
+    reader.read_exact(&mut rest)?;
+    if rest[..6] != V2_SIGNATURE[6..] {
+        return Err(invalid("missing PROXY protocol signature"));
+    }
+
+    let (version, command) = (rest[6] >> 4, rest[6] & 0x0F);
+    let family = rest[7] >> 4;
+    let len = u16::from_be_bytes([rest[8], rest[9]]) as usize;
+    if version != 2 {
+        return Err(invalid("unsupported v2 version"));
+    }
+
+    // The payload is read in full so any TLVs are consumed along with it.
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+
+    match command {
+        0x0 => return Ok(ProxyHeader::unknown()),
+        0x1 => {}
+        _ => return Err(invalid("unsupported v2 command")),
+    }
+
+    match family {
+        0x1 => {
+            let addrs = payload
+                .get(..12)
+                .ok_or_else(|| invalid("v2 payload too short"))?;
+            let ip = |at: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    addrs[at],
+                    addrs[at + 1],
+                    addrs[at + 2],
+                    addrs[at + 3],
+                ))
+            };
+            let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(ip(0), port(8))),
+                destination: Some(SocketAddr::new(ip(4), port(10))),
+            })
+        }
+        0x2 => {
+            let addrs = payload
+                .get(..36)
+                .ok_or_else(|| invalid("v2 payload too short"))?;
+            let ip = |at: usize| {
+                let octets: [u8; 16] = addrs[at..at + 16].try_into().unwrap();
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(ip(0), port(32))),
+                destination: Some(SocketAddr::new(ip(16), port(34))),
+            })
+        }
+        // AF_UNSPEC and AF_UNIX carry nothing we can turn into a socket address.
+        _ => Ok(ProxyHeader::unknown()),
+    }
+}
+
+fn invalid(msg: &str) -> XpressError {
+    XpressError::ParsingError(format!("PROXY protocol: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2_header(command: u8, family: u8, payload: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        header.extend_from_slice(payload);
+        header
+    }
+
+    #[test]
+    fn test_v1_tcp4() {
+        let mut input: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\nGET / HTTP/1.1\r\n";
+        let header = read_header(&mut input).unwrap();
+
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("198.51.100.2:443".parse().unwrap())
+        );
+        // Nothing after the header may be consumed
+        assert_eq!(input, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn test_v1_tcp6() {
+        let mut input: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 80\r\n";
+        let header = read_header(&mut input).unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:4000".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_v1_unknown() {
+        let mut input: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut input).unwrap(), ProxyHeader::unknown());
+    }
+
+    #[test]
+    fn test_v1_rejects_family_mismatch() {
+        let mut input: &[u8] = b"PROXY TCP4 2001:db8::1 2001:db8::2 4000 80\r\n";
+        assert!(read_header(&mut input).is_err());
+    }
+
+    #[test]
+    fn test_v1_rejects_unterminated_header() {
+        let long = format!("PROXY TCP4 {}", "1".repeat(200));
+        assert!(read_header(&mut long.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_v2_ipv4_with_tlv() {
+        let mut payload = vec![192, 0, 2, 1, 198, 51, 100, 2];
+        payload.extend_from_slice(&56324u16.to_be_bytes());
+        payload.extend_from_slice(&443u16.to_be_bytes());
+        payload.extend_from_slice(&[0x04, 0x00, 0x01, 0xFF]);
+        let mut input = v2_header(0x1, 0x11, &payload);
+        input.extend_from_slice(b"GET /");
+
+        let mut reader = input.as_slice();
+        let header = read_header(&mut reader).unwrap();
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("198.51.100.2:443".parse().unwrap())
+        );
+        assert_eq!(reader, b"GET /");
+    }
+
+    #[test]
+    fn test_v2_ipv6() {
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let mut payload = src.octets().to_vec();
+        payload.extend_from_slice(&dst.octets());
+        payload.extend_from_slice(&4000u16.to_be_bytes());
+        payload.extend_from_slice(&80u16.to_be_bytes());
+
+        let header = read_header(&mut v2_header(0x1, 0x21, &payload).as_slice()).unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:4000".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("[2001:db8::2]:80".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_v2_local_command() {
+        let header = read_header(&mut v2_header(0x0, 0x00, &[]).as_slice()).unwrap();
+        assert_eq!(header, ProxyHeader::unknown());
+    }
+
+    #[test]
+    fn test_v2_truncated_payload() {
+        let header = v2_header(0x1, 0x11, &[192, 0, 2, 1]);
+        assert!(read_header(&mut header.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_missing_signature() {
+        let mut input: &[u8] = b"GET / HTTP/1.1\r\n";
+        assert!(matches!(
+            read_header(&mut input),
+            Err(XpressError::ParsingError(_))
+        ));
+    }
+}
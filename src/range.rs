@@ -0,0 +1,310 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+};
+
+use crate::{
+    conditional::parse_date, method::Method, request::Request, response::Response,
+    session::generate_id, XpressError,
+};
+
+/// Most ranges served in one response. Requests for more get the whole file.
+const MAX_RANGES: usize = 16;
+
+/// Advertises `Accept-Ranges` on file responses and answers `Range`
+/// requests for them with `206 Partial Content`, or `416 Range Not
+/// Satisfiable` when none of the ranges overlap the file.
+pub(crate) fn apply(req: &Request, res: &mut Response) -> Result<(), XpressError> {
+    let length = match &res.stream {
+        Some(stream) if res.status == 200 && stream.file.is_some() => stream.length.unwrap_or(0),
+        _ => return Ok(()),
+    };
+    res.headers
+        .insert("Accept-Ranges".to_string(), "bytes".to_string());
+
+    if req.method != Method::Get {
+        return Ok(());
+    }
+    let Some(header) = req.header("Range") else {
+        return Ok(());
+    };
+    if let Some(if_range) = req.header("If-Range") {
+        if !if_range_matches(if_range, res) {
+            return Ok(());
+        }
+    }
+    // Malformed headers and other units are ignored
+    let Some(ranges) = parse_ranges(header, length) else {
+        return Ok(());
+    };
+
+    if ranges.is_empty() {
+        res.status = 416;
+        res.stream = None;
+        res.body.clear();
+        res.headers
+            .insert("Content-Range".to_string(), format!("bytes */{}", length));
+        return Ok(());
+    }
+
+    let Some(mut file) = res.stream.take().and_then(|stream| stream.file) else {
+        return Ok(());
+    };
+    res.status = 206;
+
+    if let [(start, end)] = ranges[..] {
+        file.seek(SeekFrom::Start(start))?;
+        res.headers.insert(
+            "Content-Range".to_string(),
+            format!("bytes {}-{}/{}", start, end, length),
+        );
+        res.set_stream(Box::new(file.take(end - start + 1)), Some(end - start + 1));
+        return Ok(());
+    }
+
+    let boundary = generate_id();
+    let content_type = res
+        .header("Content-Type")
+        .unwrap_or("application/octet-stream")
+        .to_string();
+
+    let mut segments = VecDeque::new();
+    let mut total = 0;
+    for (start, end) in ranges {
+        let head = format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            boundary, content_type, start, end, length
+        );
+        total += head.len() as u64 + end - start + 1;
+        segments.push_back(Segment::Bytes(Cursor::new(head.into_bytes())));
+        segments.push_back(Segment::Range {
+            start,
+            remaining: end - start + 1,
+        });
+    }
+    let tail = format!("\r\n--{}--\r\n", boundary);
+    total += tail.len() as u64;
+    segments.push_back(Segment::Bytes(Cursor::new(tail.into_bytes())));
+
+    res.headers.insert(
+        "Content-Type".to_string(),
+        format!("multipart/byteranges; boundary={}", boundary),
+    );
+    res.set_stream(Box::new(ByteRanges { file, segments }), Some(total));
+    Ok(())
+}
+
+// `If-Range` holds either a strong ETag or the exact `Last-Modified` date.
+fn if_range_matches(if_range: &str, res: &Response) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return !if_range.starts_with("W/") && res.header("ETag") == Some(if_range);
+    }
+    match (
+        parse_date(if_range),
+        res.header("Last-Modified").and_then(parse_date),
+    ) {
+        (Some(date), Some(modified)) => date == modified,
+        _ => false,
+    }
+}
+
+/// Parses a `bytes=` range header into sorted, merged, inclusive ranges
+/// within `length`. Returns `None` if the header should be ignored, and an
+/// empty list if no range is satisfiable.
+fn parse_ranges(header: &str, length: u64) -> Option<Vec<(u64, u64)>> {
+    let (unit, specs) = header.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    let specs: Vec<_> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None;
+    }
+    for spec in specs {
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        if start.is_empty() {
+            // The last `end` bytes
+            let suffix: u64 = end.parse().ok()?;
+            if suffix > 0 && length > 0 {
+                ranges.push((length.saturating_sub(suffix), length - 1));
+            }
+            continue;
+        }
+
+        let start: u64 = start.parse().ok()?;
+        let end = if end.is_empty() {
+            u64::MAX
+        } else {
+            end.parse().ok()?
+        };
+        if end < start {
+            return None;
+        }
+        if start < length {
+            ranges.push((start, end.min(length - 1)));
+        }
+    }
+
+    // Overlapping ranges would let a small request ask for the same bytes
+    // many times over
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    Some(merged)
+}
+
+enum Segment {
+    Bytes(Cursor<Vec<u8>>),
+    Range { start: u64, remaining: u64 },
+}
+
+/// A `multipart/byteranges` body read from the file as it is sent.
+struct ByteRanges {
+    file: File,
+    segments: VecDeque<Segment>,
+}
+
+impl Read for ByteRanges {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(segment) = self.segments.front_mut() {
+            let read = match segment {
+                Segment::Bytes(bytes) => bytes.read(buf)?,
+                Segment::Range { remaining: 0, .. } => 0,
+                Segment::Range { start, remaining } => {
+                    self.file.seek(SeekFrom::Start(*start))?;
+                    let max = (*remaining).min(buf.len() as u64) as usize;
+                    let read = self.file.read(&mut buf[..max])?;
+                    if read == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    *start += read as u64;
+                    *remaining -= read as u64;
+                    read
+                }
+            };
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            self.segments.pop_front();
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RequestBuilder, TestClient, Xpress};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(parse_ranges("bytes=0-4", 10), Some(vec![(0, 4)]));
+        assert_eq!(parse_ranges("bytes=5-", 10), Some(vec![(5, 9)]));
+        assert_eq!(parse_ranges("bytes=-3", 10), Some(vec![(7, 9)]));
+        assert_eq!(parse_ranges("bytes=-30", 10), Some(vec![(0, 9)]));
+        assert_eq!(parse_ranges("bytes=8-20", 10), Some(vec![(8, 9)]));
+        assert_eq!(
+            parse_ranges("bytes=6-7, 0-1,1-3", 10),
+            Some(vec![(0, 3), (6, 7)])
+        );
+        assert_eq!(parse_ranges("bytes=10-", 10), Some(vec![]));
+        assert_eq!(parse_ranges("bytes=-0", 10), Some(vec![]));
+
+        assert_eq!(parse_ranges("bytes=4-2", 10), None);
+        assert_eq!(parse_ranges("bytes=a-b", 10), None);
+        assert_eq!(parse_ranges("items=0-1", 10), None);
+        assert_eq!(parse_ranges("bytes=", 10), None);
+    }
+
+    // The file is removed when the returned guard is dropped.
+    fn client() -> (TestClient, NamedTempFile) {
+        let file = tempfile::Builder::new().suffix(".txt").tempfile().unwrap();
+        std::fs::write(file.path(), "0123456789").unwrap();
+        let mut app = Xpress::new("127.0.0.1:0");
+        let path = file.path().to_path_buf();
+        app.get("/file", move |_req, res| res.send_file(&path));
+        (TestClient::new(app), file)
+    }
+
+    fn get_range(client: &TestClient, range: &str) -> Response {
+        client.send(RequestBuilder::new("GET", "/file").header("Range", range))
+    }
+
+    #[test]
+    fn test_single_and_unsatisfiable_ranges() {
+        let (client, _file) = client();
+
+        let res = client.get("/file");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.headers["Accept-Ranges"], "bytes");
+
+        let res = get_range(&client, "bytes=2-5");
+        assert_eq!(res.status, 206);
+        assert_eq!(res.body, b"2345");
+        assert_eq!(res.headers["Content-Range"], "bytes 2-5/10");
+
+        let res = get_range(&client, "bytes=20-");
+        assert_eq!(res.status, 416);
+        assert_eq!(res.headers["Content-Range"], "bytes */10");
+        assert!(res.body.is_empty());
+    }
+
+    #[test]
+    fn test_multiple_ranges() {
+        let (client, _file) = client();
+
+        let res = get_range(&client, "bytes=0-1,-2");
+        assert_eq!(res.status, 206);
+        let content_type = &res.headers["Content-Type"];
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let expected = format!(
+            "\r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(String::from_utf8(res.body).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_if_range() {
+        let (client, _file) = client();
+        let res = client.get("/file");
+        let etag = res.headers["ETag"].clone();
+        let last_modified = res.headers["Last-Modified"].clone();
+
+        for validator in [etag.as_str(), last_modified.as_str()] {
+            let res = client.send(
+                RequestBuilder::new("GET", "/file")
+                    .header("Range", "bytes=0-0")
+                    .header("If-Range", validator),
+            );
+            assert_eq!(res.status, 206, "{}", validator);
+        }
+
+        let res = client.send(
+            RequestBuilder::new("GET", "/file")
+                .header("Range", "bytes=0-0")
+                .header("If-Range", "\"stale\""),
+        );
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body, b"0123456789");
+    }
+}
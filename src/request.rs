@@ -1,94 +1,736 @@
 use std::{
     collections::HashMap,
     io::{BufRead, BufReader, Read},
-    net::TcpStream,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, PoisonError},
+    time::SystemTime,
 };
 
-use crate::{parser::parse_query, XpressError};
+use derivative::Derivative;
 
-#[derive(Debug)]
+use crate::{
+    conditional,
+    cookie::parse_cookie_header,
+    cookie_jar::{CookieJar, CookieKeys},
+    de::from_pairs,
+    extensions::Extensions,
+    method::Method,
+    multipart::{boundary, Multipart},
+    negotiate,
+    parser::{parse_form_pairs, parse_forwarded_for, parse_query, parse_x_forwarded_for},
+    router::RouteNames,
+    session::Session,
+    XpressError,
+};
+
+#[derive(Derivative)]
+#[derivative(Debug)]
 pub struct Request {
     pub(crate) path: String,
-    pub(crate) method: String,
+    pub(crate) method: Method,
     pub(crate) headers: HashMap<String, String>,
+    /// Route params as they appear in the path, still percent-encoded. The
+    /// [`Path`](crate::Path) extractor decodes them.
     pub params: HashMap<String, String>,
+    /// Query params as sent, still encoded. The [`Query`](crate::Query)
+    /// extractor decodes them.
     pub query: HashMap<String, String>,
-    pub(crate) body: String,
+    pub(crate) query_string: String,
+    pub(crate) body: Vec<u8>,
+    /// A body left unread on the connection, see [`Request::multipart`].
+    #[derivative(Debug = "ignore")]
+    pub(crate) body_reader: Mutex<Option<Box<dyn Read + Send>>>,
+    pub(crate) version: String,
+    pub(crate) remote_addr: Option<SocketAddr>,
+    pub(crate) local_addr: Option<SocketAddr>,
+    pub(crate) client_ip: Option<IpAddr>,
+    pub(crate) connection_id: u64,
+    pub(crate) state: Arc<Extensions>,
+    pub(crate) extensions: Extensions,
+    pub(crate) cookie_keys: Option<Arc<CookieKeys>>,
+    pub(crate) routes: Arc<RouteNames>,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<crate::tls::TlsInfo>,
 }
 
-impl Request {
-    pub fn default() -> Self {
+impl Default for Request {
+    fn default() -> Self {
         Self {
             path: String::new(),
-            method: String::new(),
+            method: Method::default(),
             headers: HashMap::new(),
             params: HashMap::new(),
             query: HashMap::new(),
-            body: String::new(),
+            query_string: String::new(),
+            body: Vec::new(),
+            body_reader: Mutex::new(None),
+            version: String::new(),
+            remote_addr: None,
+            local_addr: None,
+            client_ip: None,
+            connection_id: 0,
+            state: Arc::new(Extensions::new()),
+            extensions: Extensions::new(),
+            cookie_keys: None,
+            routes: Arc::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+/// Upper bound on the size of the request line and headers.
+pub(crate) const MAX_HEAD_SIZE: usize = 64 * 1024;
+/// Largest body accepted unless the server sets its own limit.
+pub(crate) const DEFAULT_MAX_BODY_SIZE: usize = 50 * 1024 * 1024;
+
+impl Request {
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The request path, without the query string.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The raw query string, without the leading `?`.
+    pub fn query_string(&self) -> &str {
+        &self.query_string
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// The raw request body.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The request body as UTF-8 text.
+    pub fn text(&self) -> Result<&str, XpressError> {
+        std::str::from_utf8(&self.body)
+            .map_err(|_| XpressError::bad_request("Request body is not valid UTF-8"))
+    }
+
+    /// Looks up a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The offered media type the client prefers according to its `Accept`
+    /// header and q-values, or `None` if it accepts none of them.
+    ///
+    /// ```ignore
+    /// match req.accepts(&["application/json", "text/html"]) { ... }
+    /// ```
+    pub fn accepts<'o>(&self, offers: &[&'o str]) -> Option<&'o str> {
+        negotiate::preferred(self.header("Accept"), offers)
+    }
+
+    /// Evaluates `If-Match`, `If-None-Match`, `If-Modified-Since` and
+    /// `If-Unmodified-Since` against the resource's current validators,
+    /// failing with `412 Precondition Failed` (or `304 Not Modified` for
+    /// `GET` and `HEAD`) when the request should not proceed. Pass `None`
+    /// for both if the resource doesn't exist yet.
+    ///
+    /// Call it before modifying anything; `GET` and `HEAD` responses are
+    /// also checked automatically once the handler has run.
+    ///
+    /// ```ignore
+    /// let doc = load(id)?;
+    /// req.check_preconditions(Some(&doc.etag()), Some(doc.updated_at))?;
+    /// save(id, req.from_json()?)?;
+    /// ```
+    pub fn check_preconditions(
+        &self,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+    ) -> Result<(), XpressError> {
+        let exists = etag.is_some() || last_modified.is_some();
+        match conditional::evaluate(self, etag, last_modified, exists) {
+            Some(304) => Err(XpressError::http(304, "Not Modified")),
+            Some(status) => Err(XpressError::http(status, "Precondition Failed")),
+            None => Ok(()),
+        }
+    }
+
+    /// Cookies sent in the `Cookie` header, by name.
+    pub fn cookies(&self) -> HashMap<String, String> {
+        self.header("Cookie")
+            .map(parse_cookie_header)
+            .unwrap_or_default()
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies().remove(name)
+    }
+
+    /// The request's cookies, including signed and encrypted ones.
+    pub fn cookie_jar(&self) -> CookieJar {
+        CookieJar::new(self.cookies(), self.cookie_keys.clone())
+    }
+
+    /// The session loaded by the [`sessions`](crate::sessions) middleware.
+    pub fn session(&self) -> Result<Session, XpressError> {
+        self.extensions
+            .get::<Session>()
+            .cloned()
+            .ok_or_else(|| XpressError::Custom("Session middleware is not installed".to_string()))
+    }
+
+    /// The HTTP version from the request line, e.g. `HTTP/1.1`.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Address of the peer that opened the connection.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// Local address the connection was accepted on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// IP of the originating client. This is the peer address unless the
+    /// peer is a trusted proxy, in which case forwarding headers are honoured.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    /// Identifier of the connection this request arrived on, unique for the
+    /// lifetime of the server.
+    pub fn connection_id(&self) -> u64 {
+        self.connection_id
+    }
+
+    /// Application state registered with [`Xpress::with_state`](crate::Xpress::with_state).
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get::<Arc<T>>().map(Arc::as_ref)
+    }
+
+    /// Builds the path of a named route, like [`Xpress::url_for`](crate::Xpress::url_for).
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, XpressError> {
+        self.routes.url_for(name, params)
+    }
+
+    /// Values attached to this request, typically by middleware.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Sets the path and query from a request target such as `/users?page=2`.
+    pub(crate) fn set_target(&mut self, target: &str) {
+        if let Some((path, query)) = target.split_once('?') {
+            self.path = path.to_string();
+            self.query = parse_query(query);
+            self.query_string = query.to_string();
+        } else {
+            self.path = target.to_string();
+            self.query.clear();
+            self.query_string.clear();
         }
     }
 
+    pub(crate) fn resolve_client_ip(&self, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+        let peer = self.remote_addr?.ip();
+        if !trusted_proxies.contains(&peer) {
+            return Some(peer);
+        }
+
+        let hops = if let Some(forwarded) = self.header("Forwarded") {
+            parse_forwarded_for(forwarded)
+        } else if let Some(forwarded_for) = self.header("X-Forwarded-For") {
+            parse_x_forwarded_for(forwarded_for)
+        } else {
+            return Some(peer);
+        };
+
+        // Walk back from the nearest hop, skipping the proxies we trust.
+        hops.iter()
+            .rev()
+            .find(|ip| !trusted_proxies.contains(ip))
+            .or(hops.first())
+            .copied()
+            .or(Some(peer))
+    }
+
+    /// Details of the TLS session, or `None` for plaintext connections.
+    #[cfg(feature = "tls")]
+    pub fn tls(&self) -> Option<&crate::tls::TlsInfo> {
+        self.tls.as_ref()
+    }
+
+    /// Whether the `Content-Type` media type is `media_type`, ignoring
+    /// parameters such as `charset` and case.
+    pub fn is_content_type(&self, media_type: &str) -> bool {
+        self.header("Content-Type").is_some_and(|content_type| {
+            content_type
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .eq_ignore_ascii_case(media_type)
+        })
+    }
+
+    /// Deserializes an `application/x-www-form-urlencoded` body. Repeated
+    /// keys fill `Vec` fields.
+    ///
+    /// Fails with `415 Unsupported Media Type` for other content types and
+    /// with `400 Bad Request` when the form does not match `T`.
+    pub fn form<T: serde::de::DeserializeOwned>(&self) -> Result<T, XpressError> {
+        if !self.is_content_type("application/x-www-form-urlencoded") {
+            return Err(XpressError::unsupported_media_type(
+                "Expected an application/x-www-form-urlencoded body",
+            ));
+        }
+
+        let pairs = parse_form_pairs(self.text()?);
+        from_pairs(pairs.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            .map_err(|e| XpressError::bad_request(format!("Invalid form body: {}", e)))
+    }
+
+    /// A parser for a `multipart/form-data` body, such as file uploads.
+    /// Fails with `415 Unsupported Media Type` for other content types.
+    ///
+    /// On a server, multipart bodies are not read before the handler runs.
+    /// The parser reads them from the connection as it goes, so only its
+    /// limits bound the upload, and [`Request::body`] stays empty. The body
+    /// can then be read only once.
+    pub fn multipart(&self) -> Result<Multipart<Box<dyn Read + '_>>, XpressError> {
+        let boundary = self
+            .header("Content-Type")
+            .and_then(boundary)
+            .ok_or_else(|| {
+                XpressError::unsupported_media_type("Expected a multipart/form-data body")
+            })?;
+        let pending = self
+            .body_reader
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        let reader: Box<dyn Read + '_> = match pending {
+            Some(reader) => reader,
+            None => Box::new(&self.body[..]),
+        };
+        Ok(Multipart::new(reader, &boundary))
+    }
+
+    /// Deserializes a JSON body. Fails with `400 Bad Request` when the body
+    /// is empty, malformed or does not match `T`.
+    #[allow(clippy::wrong_self_convention)]
     pub fn from_json<T: serde::de::DeserializeOwned>(&self) -> Result<T, XpressError> {
         if self.body.is_empty() {
-            return Err(XpressError::Custom("Empty request body".to_string()));
+            return Err(XpressError::bad_request("Empty request body"));
         }
 
-        match serde_json::from_str::<T>(&self.body) {
-            Ok(user) => Ok(user),
-            Err(e) => Err(XpressError::JsonError(e)),
-        }
+        serde_json::from_slice::<T>(&self.body).map_err(|e| {
+            XpressError::bad_request(format!("Invalid JSON body: {}", e)).with_source(e)
+        })
     }
 }
 
-impl TryFrom<&mut BufReader<&mut TcpStream>> for Request {
+impl<R: Read> TryFrom<&mut BufReader<R>> for Request {
     type Error = XpressError;
 
-    fn try_from(buf_reader: &mut BufReader<&mut TcpStream>) -> Result<Self, Self::Error> {
-        let mut request = Request::default();
-        let mut lines = buf_reader.lines();
+    fn try_from(buf_reader: &mut BufReader<R>) -> Result<Self, Self::Error> {
+        Request::parse(buf_reader)
+    }
+}
+
+impl Request {
+    /// Reads one request, head and body, from any buffered reader such as a
+    /// connection, a file or a byte slice. For data arriving in pieces, use
+    /// [`RequestParser`](crate::RequestParser).
+    ///
+    /// Bodies over 50 MiB fail with `413 Content Too Large`.
+    pub fn parse<R: BufRead>(buf_reader: &mut R) -> Result<Self, XpressError> {
+        Request::parse_limited(buf_reader, DEFAULT_MAX_BODY_SIZE)
+    }
+
+    /// Like [`Request::parse`], rejecting a `Content-Length` over
+    /// `max_body_size` before any of the body is read.
+    pub(crate) fn parse_limited<R: BufRead>(
+        buf_reader: &mut R,
+        max_body_size: usize,
+    ) -> Result<Self, XpressError> {
+        let mut request = Request::read_head(buf_reader)?;
+        request.read_body(buf_reader, max_body_size)?;
+        Ok(request)
+    }
+
+    /// Like [`Request::parse_limited`], but leaves a `multipart/form-data`
+    /// body unread in `reader`, for [`Request::multipart`] to stream. Its size
+    /// is then bounded by the multipart limits rather than `max_body_size`.
+    /// Encoded bodies are still read, for decompression to work on.
+    pub(crate) fn parse_streaming<R: BufRead + Send + 'static>(
+        mut reader: R,
+        max_body_size: usize,
+    ) -> Result<Self, XpressError> {
+        let mut request = Request::read_head(&mut reader)?;
+        let multipart = request.header("Content-Type").and_then(boundary).is_some();
+        if multipart && request.header("Content-Encoding").is_none() {
+            let length = request.content_length()?;
+            request.body_reader = Mutex::new(Some(Box::new(reader.take(length as u64))));
+        } else {
+            request.read_body(&mut reader, max_body_size)?;
+        }
+        Ok(request)
+    }
+
+    fn read_head<R: BufRead>(buf_reader: &mut R) -> Result<Self, XpressError> {
+        let mut head = String::new();
+        loop {
+            // Bounded, so a line that never ends can't grow past the limit
+            let remaining = (MAX_HEAD_SIZE + 1 - head.len()) as u64;
+            let read = buf_reader.take(remaining).read_line(&mut head)?;
+            if read == 0 {
+                if head.is_empty() {
+                    return Err(XpressError::ParsingError("Missing request line".into()));
+                }
+                return Err(XpressError::ParsingError("Incomplete request head".into()));
+            }
+            if head.len() > MAX_HEAD_SIZE {
+                return Err(XpressError::ParsingError("Request head too large".into()));
+            }
+            if head.trim().is_empty() {
+                // Blank lines before the request line are ignored
+                head.clear();
+            } else if head.ends_with("\n\n") || head.ends_with("\n\r\n") {
+                break;
+            }
+        }
+        Request::parse_head(&head)
+    }
+
+    fn read_body<R: BufRead>(
+        &mut self,
+        buf_reader: &mut R,
+        max_body_size: usize,
+    ) -> Result<(), XpressError> {
+        let length = self.content_length()?;
+        if length > max_body_size {
+            return Err(XpressError::payload_too_large());
+        }
+        // Grows with the data actually received rather than the declared length
+        buf_reader.take(length as u64).read_to_end(&mut self.body)?;
+        if self.body.len() < length {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(())
+    }
+
+    /// Parses a complete request held in memory.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, XpressError> {
+        Request::parse(&mut bytes)
+    }
 
+    /// Parses the request line and headers, up to the blank line.
+    pub(crate) fn parse_head(head: &str) -> Result<Self, XpressError> {
+        let mut lines = head.lines();
         let request_line = lines
             .next()
-            .ok_or_else(|| XpressError::ParsingError("Missing request line".into()))??;
+            .filter(|line| !line.is_empty())
+            .ok_or_else(|| XpressError::ParsingError("Missing request line".into()))?;
 
         let mut parts = request_line.split_whitespace();
-        request.method = parts.next().unwrap_or("").to_string();
-        let raw_path = parts.next().unwrap_or("");
-        if request.method.is_empty() || raw_path.is_empty() {
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
             return Err(XpressError::ParsingError(format!(
                 "Malformed request line: {}",
                 request_line
             )));
+        };
+
+        // Only origin-form targets, and `*` for `OPTIONS`, are served
+        if !target.starts_with('/') && target != "*" {
+            return Err(XpressError::ParsingError(format!(
+                "Invalid request target: {}",
+                target
+            )));
         }
 
-        if let Some((path, query)) = raw_path.split_once('?') {
-            request.path = path.to_string();
-            request.query = parse_query(query);
-        } else {
-            request.path = raw_path.to_string();
+        let mut request = Request {
+            method: method.parse()?,
+            version: parts.next().unwrap_or("HTTP/1.0").to_string(),
+            ..Request::default()
+        };
+        request.set_target(target);
+
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| XpressError::ParsingError(format!("Malformed header: {}", line)))?;
+            request.append_header(key.trim(), value.trim());
         }
 
-        for line in lines.by_ref() {
-            let line = line?;
-            if line.is_empty() {
-                break;
+        Ok(request)
+    }
+
+    /// Adds a header, joining it with any earlier one of the same name in
+    /// any case, so lookups such as `X-Forwarded-For` see every value.
+    fn append_header(&mut self, key: &str, value: &str) {
+        match self
+            .headers
+            .iter_mut()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(key))
+        {
+            Some((existing, joined)) => {
+                let separator = if existing.eq_ignore_ascii_case("Cookie") {
+                    "; "
+                } else {
+                    ", "
+                };
+                joined.push_str(separator);
+                joined.push_str(value);
             }
-            if let Some((key, value)) = line.split_once(": ") {
-                request.headers.insert(key.to_string(), value.to_string());
+            None => {
+                self.headers.insert(key.to_string(), value.to_string());
             }
         }
+    }
 
-        if let Some(content_length) = request
-            .headers
-            .get("Content-Length")
-            .and_then(|cl| cl.parse::<usize>().ok())
-        {
-            let mut body = vec![0; content_length];
-            buf_reader.read_exact(&mut body)?;
-            request.body = String::from_utf8(body)
-                .map_err(|_| XpressError::ParsingError("Invalid UTF-8 in body".into()))?;
+    /// The declared body length, zero when there is no `Content-Length`.
+    pub(crate) fn content_length(&self) -> Result<usize, XpressError> {
+        self.header("Content-Length").map_or(Ok(0), |length| {
+            length.parse().map_err(|_| {
+                XpressError::ParsingError(format!("Invalid Content-Length: {}", length))
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_from(peer: &str, headers: &[(&str, &str)]) -> Request {
+        let mut req = Request {
+            remote_addr: Some(peer.parse().unwrap()),
+            ..Request::default()
+        };
+        for (key, value) in headers {
+            req.headers.insert(key.to_string(), value.to_string());
+        }
+        req
+    }
+
+    fn ips(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_parse_from_bytes() {
+        let raw =
+            b"POST /users?page=2 HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\n\r\nhello";
+        let req = Request::from_bytes(raw).unwrap();
+        assert_eq!(req.method(), &Method::Post);
+        assert_eq!(req.path, "/users");
+        assert_eq!(req.query["page"], "2");
+        assert_eq!(req.version(), "HTTP/1.1");
+        assert_eq!(req.header("host"), Some("example.com"));
+        assert_eq!(req.body(), b"hello");
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let line = format!("GET / HTTP/1.1\r\nX-Long: {}", "a".repeat(MAX_HEAD_SIZE));
+        let mut reader = line.as_bytes();
+        assert!(Request::parse(&mut reader).is_err());
+        // The oversized line was not read to its end
+        assert!(!reader.is_empty());
+
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world";
+        let err = Request::parse_limited(&mut &raw[..], 10).unwrap_err();
+        assert_eq!(err.status_code(), 413);
+        assert!(Request::parse_limited(&mut &raw[..], 11).is_ok());
+        assert!(Request::parse_limited(&mut &raw[..raw.len() - 1], 11).is_err());
+    }
+
+    #[test]
+    fn test_multipart_body_is_streamed() {
+        const PART_SIZE: u64 = 1024 * 1024;
+        let part_head =
+            "--b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"f.bin\"\r\n\r\n";
+        let tail = "\r\n--b--\r\n";
+        let head = format!(
+            "POST /upload HTTP/1.1\r\n\
+             Content-Type: multipart/form-data; boundary=b\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            part_head.len() as u64 + PART_SIZE + tail.len() as u64,
+            part_head
+        );
+        let body = std::io::Cursor::new(head)
+            .chain(std::io::repeat(b'x').take(PART_SIZE))
+            .chain(tail.as_bytes());
+
+        let req = Request::parse_streaming(BufReader::new(body), 1024).unwrap();
+        assert!(req.body().is_empty());
+        let dir = tempfile::tempdir().unwrap();
+        let form = req
+            .multipart()
+            .unwrap()
+            .spool_files_to(dir.path())
+            .form_data()
+            .unwrap();
+        assert_eq!(form.file("f").unwrap().size(), PART_SIZE);
+        assert!(req.body().is_empty());
+
+        // The body can be read only once
+        assert!(req.multipart().unwrap().form_data().is_err());
+    }
+
+    #[test]
+    fn test_form() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Signup {
+            name: String,
+            age: u8,
+            #[serde(default)]
+            interests: Vec<String>,
         }
 
-        Ok(request)
+        let mut req = request_from(
+            "127.0.0.1:1234",
+            &[(
+                "Content-Type",
+                "application/x-www-form-urlencoded; charset=UTF-8",
+            )],
+        );
+        req.body = "name=Ada+Lovelace&age=36&interests=math&interests=poetry%21".into();
+        assert_eq!(
+            req.form::<Signup>().unwrap(),
+            Signup {
+                name: "Ada Lovelace".into(),
+                age: 36,
+                interests: vec!["math".into(), "poetry!".into()],
+            }
+        );
+
+        req.body = "name=Ada&age=old".into();
+        assert_eq!(req.form::<Signup>().unwrap_err().status_code(), 400);
+
+        req.headers
+            .insert("Content-Type".into(), "application/json".into());
+        assert_eq!(req.form::<Signup>().unwrap_err().status_code(), 415);
+    }
+
+    #[test]
+    fn test_from_json() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct User {
+            name: String,
+        }
+
+        let mut req = request_from("127.0.0.1:1234", &[]);
+        req.body = br#"{"name":"Ada"}"#.to_vec();
+        assert_eq!(
+            req.from_json::<User>().unwrap(),
+            User { name: "Ada".into() }
+        );
+
+        for body in [&b""[..], b"{\"name\":", br#"{"name":1}"#] {
+            req.body = body.to_vec();
+            assert_eq!(req.from_json::<User>().unwrap_err().status_code(), 400);
+        }
+    }
+
+    #[test]
+    fn test_multipart() {
+        let mut req = request_from(
+            "127.0.0.1:1234",
+            &[("Content-Type", "multipart/form-data; boundary=b")],
+        );
+        req.body = b"--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--b--".to_vec();
+        let form = req.multipart().unwrap().form_data().unwrap();
+        assert_eq!(form.field("a"), Some("1"));
+
+        req.headers
+            .insert("Content-Type".into(), "text/plain".into());
+        assert_eq!(req.multipart().unwrap_err().status_code(), 415);
+    }
+
+    #[test]
+    fn test_header_lookup_ignores_case() {
+        let req = request_from("127.0.0.1:1234", &[("content-type", "text/plain")]);
+        assert_eq!(req.header("Content-Type"), Some("text/plain"));
+        assert_eq!(req.header("Accept"), None);
+    }
+
+    #[test]
+    fn test_duplicate_headers_are_joined() {
+        let raw =
+            b"GET / HTTP/1.1\r\nX-Forwarded-For: 1.2.3.4\r\nx-forwarded-for: 198.51.100.1\r\n\
+                    Cookie: a=1\r\ncookie: b=2\r\n\r\n";
+        let mut req = Request::from_bytes(raw).unwrap();
+        assert_eq!(req.headers.len(), 2);
+        assert_eq!(req.header("X-Forwarded-For"), Some("1.2.3.4, 198.51.100.1"));
+        assert_eq!(req.cookie("b").as_deref(), Some("2"));
+
+        // The value appended last is the nearest hop, whichever case it used
+        req.remote_addr = Some("10.0.0.1:4000".parse().unwrap());
+        assert_eq!(
+            req.resolve_client_ip(&ips(&["10.0.0.1"])),
+            Some(ips(&["198.51.100.1"])[0])
+        );
+    }
+
+    #[test]
+    fn test_client_ip_ignores_headers_from_untrusted_peer() {
+        let req = request_from("203.0.113.9:4000", &[("X-Forwarded-For", "198.51.100.1")]);
+        assert_eq!(
+            req.resolve_client_ip(&ips(&["10.0.0.1"])),
+            Some(ips(&["203.0.113.9"])[0])
+        );
+    }
+
+    #[test]
+    fn test_client_ip_from_x_forwarded_for() {
+        let req = request_from(
+            "10.0.0.1:4000",
+            &[("X-Forwarded-For", "198.51.100.1, 10.0.0.2")],
+        );
+        let trusted = ips(&["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(
+            req.resolve_client_ip(&trusted),
+            Some(ips(&["198.51.100.1"])[0])
+        );
+    }
+
+    #[test]
+    fn test_client_ip_skips_spoofed_leftmost_entry() {
+        let req = request_from(
+            "10.0.0.1:4000",
+            &[("X-Forwarded-For", "1.2.3.4, 198.51.100.1")],
+        );
+        assert_eq!(
+            req.resolve_client_ip(&ips(&["10.0.0.1"])),
+            Some(ips(&["198.51.100.1"])[0])
+        );
+    }
+
+    #[test]
+    fn test_client_ip_prefers_forwarded_header() {
+        let req = request_from(
+            "10.0.0.1:4000",
+            &[
+                ("Forwarded", "for=\"[2001:db8::17]:4711\";proto=https"),
+                ("X-Forwarded-For", "198.51.100.1"),
+            ],
+        );
+        assert_eq!(
+            req.resolve_client_ip(&ips(&["10.0.0.1"])),
+            Some(ips(&["2001:db8::17"])[0])
+        );
     }
 }
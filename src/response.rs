@@ -1,13 +1,54 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fs::File, io::Read, path::Path};
 
-use crate::error::XpressError;
+use derivative::Derivative;
 
-#[derive(Debug)]
+use crate::{
+    conditional,
+    cookie::Cookie,
+    error::XpressError,
+    extract::Json,
+    mime,
+    negotiate::Format,
+    request::Request,
+    sse::{self, EventSender, EventsCallback},
+    websocket::WsHandler,
+};
+
+/// A body written from a reader instead of [`Response::body`]. Without a
+/// known length it is sent with chunked transfer encoding.
+pub(crate) struct StreamBody {
+    pub(crate) reader: Box<dyn Read + Send>,
+    pub(crate) length: Option<u64>,
+    /// The file behind `reader` when the body is a whole file, so byte
+    /// ranges of it can be served instead.
+    pub(crate) file: Option<File>,
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
 pub struct Response {
     pub status: u16,
     pub headers: HashMap<String, String>,
     pub body: Vec<u8>,
     pub sent: bool,
+    /// Cookies sent as separate `Set-Cookie` headers.
+    pub cookies: Vec<Cookie>,
+    #[derivative(Debug = "ignore")]
+    pub(crate) stream: Option<StreamBody>,
+    #[derivative(Debug = "ignore")]
+    pub(crate) events: Option<EventsCallback>,
+    /// Set on an accepted WebSocket handshake, run once the `101` is sent.
+    #[derivative(Debug = "ignore")]
+    pub(crate) upgrade: Option<WsHandler>,
+    #[cfg(feature = "template")]
+    #[derivative(Debug = "ignore")]
+    pub(crate) templates: Option<std::sync::Arc<crate::template::Templates>>,
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Response {
@@ -20,6 +61,12 @@ impl Response {
             headers,
             body: Vec::new(),
             sent: false,
+            cookies: Vec::new(),
+            stream: None,
+            events: None,
+            upgrade: None,
+            #[cfg(feature = "template")]
+            templates: None,
         }
     }
 
@@ -27,18 +74,223 @@ impl Response {
         self.status = status;
     }
 
+    /// Looks up a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Adds `header` to the `Vary` header, telling caches the response
+    /// depends on that request header.
+    pub fn vary(&mut self, header: &str) {
+        let existing = self
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("Vary"))
+            .map(|(key, value)| (key.clone(), value.clone()));
+        match existing {
+            Some((_, value))
+                if value
+                    .split(',')
+                    .any(|v| v.trim() == "*" || v.trim().eq_ignore_ascii_case(header)) => {}
+            Some((key, value)) => {
+                self.headers.insert(key, format!("{}, {}", value, header));
+            }
+            None => {
+                self.headers.insert("Vary".to_string(), header.to_string());
+            }
+        }
+    }
+
     pub fn send(&mut self, body: impl Into<Vec<u8>>) -> Result<(), XpressError> {
         self.body = body.into();
+        self.stream = None;
+        self.events = None;
         Ok(())
     }
 
     pub fn json<T: serde::Serialize>(&mut self, body: &T) -> Result<(), XpressError> {
         self.headers
             .insert("Content-Type".to_string(), "application/json".to_string());
-        self.body = serde_json::to_vec(body).map_err(XpressError::JsonError)?;
+        self.send(serde_json::to_vec(body).map_err(XpressError::JsonError)?)
+    }
+
+    /// Renders a template configured with
+    /// [`Xpress::templates`](crate::Xpress::templates), with `context`
+    /// serialized into its variables.
+    #[cfg(feature = "template")]
+    pub fn render<S: serde::Serialize>(
+        &mut self,
+        name: &str,
+        context: &S,
+    ) -> Result<(), XpressError> {
+        let templates = self
+            .templates
+            .clone()
+            .ok_or_else(|| XpressError::Custom("No templates configured".to_string()))?;
+        let html = templates.render(name, context)?;
+        self.headers.insert(
+            "Content-Type".to_string(),
+            mime::from_path(Path::new(name)).to_string(),
+        );
+        self.send(html)
+    }
+
+    /// Starts choosing a renderer by the request's `Accept` header. See
+    /// [`Format`].
+    pub fn format<'a>(&'a mut self, req: &'a Request) -> Format<'a> {
+        Format::new(req, self)
+    }
+
+    /// Redirects to `url` with `302 Found`.
+    pub fn redirect(&mut self, url: &str) -> Result<(), XpressError> {
+        self.redirect_with(302, url)
+    }
+
+    /// Redirects to `url` with a 3xx status, e.g. `301` for moved pages or
+    /// `303` after a form submission.
+    pub fn redirect_with(&mut self, status: u16, url: &str) -> Result<(), XpressError> {
+        if !(300..400).contains(&status) {
+            return Err(XpressError::Custom(format!(
+                "{} is not a redirect status",
+                status
+            )));
+        }
+        if url.contains(['\r', '\n']) {
+            return Err(XpressError::Custom(
+                "Redirect URL contains a line break".to_string(),
+            ));
+        }
+        self.status = status;
+        self.headers.insert("Location".to_string(), url.to_string());
+        self.send(format!("Redirecting to {}", url))
+    }
+
+    /// Holds the connection open as a `text/event-stream` and runs
+    /// `callback` on its own thread to send events. Keep-alive comments are
+    /// sent while it is idle, and sending fails once the client has gone,
+    /// at which point the callback should return.
+    ///
+    /// ```ignore
+    /// res.sse(move |sender| {
+    ///     for update in updates {
+    ///         sender.send(Event::new(update.json).event("update"))?;
+    ///     }
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn sse<F>(&mut self, callback: F)
+    where
+        F: FnOnce(&EventSender<'_>) -> Result<(), XpressError> + Send + 'static,
+    {
+        self.headers
+            .insert("Content-Type".to_string(), "text/event-stream".to_string());
+        self.headers
+            .insert("Cache-Control".to_string(), "no-cache".to_string());
+        // Stops nginx from buffering the stream
+        self.headers
+            .insert("X-Accel-Buffering".to_string(), "no".to_string());
+        self.body.clear();
+        self.stream = None;
+        self.events = Some(Box::new(callback));
+    }
+
+    /// Sends whatever `reader` produces as the body, in chunks, without
+    /// buffering it in memory.
+    pub fn stream(&mut self, reader: impl Read + Send + 'static) {
+        self.set_stream(Box::new(reader), None);
+    }
+
+    /// Streams a file as the body, with a `Content-Type` inferred from its
+    /// extension.
+    pub fn send_file(&mut self, path: impl AsRef<Path>) -> Result<(), XpressError> {
+        let path = path.as_ref();
+        let not_found = || XpressError::FileNotFound(path.display().to_string());
+        let file = File::open(path).map_err(|_| not_found())?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(not_found());
+        }
+
+        self.headers.insert(
+            "Content-Type".to_string(),
+            mime::from_path(path).to_string(),
+        );
+        if let Ok(modified) = metadata.modified() {
+            self.headers.insert(
+                "Last-Modified".to_string(),
+                httpdate::fmt_http_date(modified),
+            );
+            self.headers.insert(
+                "ETag".to_string(),
+                conditional::file_etag(metadata.len(), modified),
+            );
+        }
+        self.set_stream(Box::new(file.try_clone()?), Some(metadata.len()));
+        if let Some(stream) = &mut self.stream {
+            stream.file = Some(file);
+        }
         Ok(())
     }
 
+    pub(crate) fn set_stream(&mut self, reader: Box<dyn Read + Send>, length: Option<u64>) {
+        self.body.clear();
+        self.events = None;
+        self.stream = Some(StreamBody {
+            reader,
+            length,
+            file: None,
+        });
+    }
+
+    /// Reads a streamed body, or runs an event stream to its end, into
+    /// [`Response::body`].
+    pub(crate) fn buffer_stream(&mut self) -> Result<(), XpressError> {
+        if let Some(mut stream) = self.stream.take() {
+            self.body.clear();
+            stream.reader.read_to_end(&mut self.body)?;
+        }
+        if let Some(events) = self.events.take() {
+            let mut body = Vec::new();
+            sse::run(events, &mut body, sse::KEEP_ALIVE)?;
+            self.body = body;
+        }
+        Ok(())
+    }
+
+    /// Adds a `Set-Cookie` header. Fails if the cookie's name, value or
+    /// attributes contain characters not allowed in the header.
+    pub fn set_cookie(&mut self, cookie: Cookie) -> Result<(), XpressError> {
+        cookie.validate()?;
+        self.cookies.retain(|c| c.name() != cookie.name());
+        self.cookies.push(cookie);
+        Ok(())
+    }
+
+    /// Tells the client to delete a cookie set with `Path=/`. For other
+    /// paths or domains, pass [`Cookie::expire`] to [`Response::set_cookie`].
+    pub fn clear_cookie(&mut self, name: &str) -> Result<(), XpressError> {
+        self.set_cookie(Cookie::new(name, "").path("/").expire())
+    }
+
+    /// Takes over the status, headers and body of a response built by a
+    /// handler, keeping any headers and cookies set earlier (e.g. by
+    /// middleware).
+    pub(crate) fn merge(&mut self, other: Response) {
+        self.status = other.status;
+        self.headers.extend(other.headers);
+        self.body = other.body;
+        self.stream = other.stream;
+        self.events = other.events;
+        self.upgrade = other.upgrade;
+        for cookie in other.cookies {
+            self.cookies.retain(|c| c.name() != cookie.name());
+            self.cookies.push(cookie);
+        }
+    }
+
     pub fn html(&mut self, path: &str) -> Result<(), XpressError> {
         self.headers
             .insert("Content-Type".to_string(), "text/html".to_string());
@@ -47,3 +299,215 @@ impl Response {
         Ok(())
     }
 }
+
+/// The standard reason phrase for a status code, or an empty string for
+/// codes without one.
+pub(crate) fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        409 => "Conflict",
+        410 => "Gone",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        422 => "Unprocessable Content",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+/// Values a handler can return instead of writing to `&mut Response`.
+///
+/// Returning `Err` hands the error to the server's error handling, exactly as
+/// a handler returning `Err` would.
+pub trait IntoResponse {
+    fn into_response(self) -> Result<Response, XpressError>;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Result<Response, XpressError> {
+        Ok(self)
+    }
+}
+
+impl IntoResponse for () {
+    fn into_response(self) -> Result<Response, XpressError> {
+        Ok(Response::new())
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Result<Response, XpressError> {
+        self.to_string().into_response()
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Result<Response, XpressError> {
+        let mut res = Response::new();
+        res.send(self)?;
+        Ok(res)
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Result<Response, XpressError> {
+        let mut res = Response::new();
+        res.headers.insert(
+            "Content-Type".to_string(),
+            "application/octet-stream".to_string(),
+        );
+        res.send(self)?;
+        Ok(res)
+    }
+}
+
+impl<T: serde::Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Result<Response, XpressError> {
+        let mut res = Response::new();
+        res.json(&self.0)?;
+        Ok(res)
+    }
+}
+
+impl IntoResponse for XpressError {
+    fn into_response(self) -> Result<Response, XpressError> {
+        Err(self)
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Result<Response, XpressError> {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (u16, T) {
+    fn into_response(self) -> Result<Response, XpressError> {
+        let mut res = self.1.into_response()?;
+        res.status = self.0;
+        Ok(res)
+    }
+}
+
+impl<K, V, T, const N: usize> IntoResponse for ([(K, V); N], T)
+where
+    K: Into<String>,
+    V: Into<String>,
+    T: IntoResponse,
+{
+    fn into_response(self) -> Result<Response, XpressError> {
+        let mut res = self.1.into_response()?;
+        res.headers
+            .extend(self.0.into_iter().map(|(k, v)| (k.into(), v.into())));
+        Ok(res)
+    }
+}
+
+impl<K, V, T, const N: usize> IntoResponse for (u16, [(K, V); N], T)
+where
+    K: Into<String>,
+    V: Into<String>,
+    T: IntoResponse,
+{
+    fn into_response(self) -> Result<Response, XpressError> {
+        let mut res = (self.1, self.2).into_response()?;
+        res.status = self.0;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vary_merges() {
+        let mut res = Response::new();
+        res.headers.insert("vary".to_string(), "Origin".to_string());
+        res.vary("Accept-Encoding");
+        res.vary("accept-encoding");
+        assert_eq!(res.headers["vary"], "Origin, Accept-Encoding");
+    }
+
+    #[test]
+    fn test_string_into_response() {
+        let res = "hello".into_response().unwrap();
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body, b"hello");
+        assert_eq!(res.headers["Content-Type"], "text/plain");
+    }
+
+    #[test]
+    fn test_status_and_json_tuple() {
+        let res = (201, Json(vec![1, 2, 3])).into_response().unwrap();
+        assert_eq!(res.status, 201);
+        assert_eq!(res.body, b"[1,2,3]");
+        assert_eq!(res.headers["Content-Type"], "application/json");
+    }
+
+    #[test]
+    fn test_status_headers_tuple() {
+        let res = (202, [("X-Request-Id", "abc")], Vec::from("ok"))
+            .into_response()
+            .unwrap();
+        assert_eq!(res.status, 202);
+        assert_eq!(res.headers["X-Request-Id"], "abc");
+        assert_eq!(res.headers["Content-Type"], "application/octet-stream");
+    }
+
+    #[test]
+    fn test_set_cookie_replaces_same_name() {
+        let mut res = Response::new();
+        res.set_cookie(Cookie::new("a", "1")).unwrap();
+        res.set_cookie(Cookie::new("b", "2")).unwrap();
+        res.set_cookie(Cookie::new("a", "3")).unwrap();
+        assert!(res.set_cookie(Cookie::new("c", "x;y")).is_err());
+
+        let values: Vec<_> = res.cookies.iter().map(|c| c.to_string()).collect();
+        assert_eq!(values, ["b=2", "a=3"]);
+    }
+
+    #[test]
+    fn test_result_into_response() {
+        let ok: Result<&str, XpressError> = Ok("fine");
+        assert_eq!(ok.into_response().unwrap().body, b"fine");
+
+        let err: Result<&str, XpressError> = Err(XpressError::NotFound("/x".into()));
+        assert!(matches!(err.into_response(), Err(XpressError::NotFound(_))));
+
+        // Error types that render themselves become regular responses
+        let rendered: Result<&str, (u16, &str)> = Err((409, "taken"));
+        let res = rendered.into_response().unwrap();
+        assert_eq!(res.status, 409);
+        assert_eq!(res.body, b"taken");
+    }
+}
@@ -1,22 +1,27 @@
-use std::collections::HashMap;
-
-use crate::{request::Request, response::Response, XpressError};
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    parser::{parse_path_segments, percent_encode},
+    request::Request,
+    response::Response,
+    XpressError,
+};
 use derivative::Derivative;
 
 pub(crate) type Handler =
     Box<dyn Fn(&Request, &mut Response) -> Result<(), XpressError> + Send + Sync>;
 
 #[derive(Derivative)]
-#[derivative(Debug)]
-struct TrieNode {
+#[derivative(Debug(bound = ""))]
+struct TrieNode<H> {
     route_segment: Segment,
     #[derivative(Debug = "ignore")]
-    handler: Option<Handler>,
+    handler: Option<H>,
     is_leaf: bool,
-    children: Box<NodeChildren>,
+    children: Box<NodeChildren<H>>,
 }
 
-impl TrieNode {
+impl<H> TrieNode<H> {
     fn new(route_segment: Segment) -> Self {
         TrieNode {
             route_segment,
@@ -27,13 +32,14 @@ impl TrieNode {
     }
 }
 
-#[derive(Debug)]
-struct NodeChildren {
-    static_nodes: HashMap<String, TrieNode>,
-    dynamic_node: Option<TrieNode>,
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+struct NodeChildren<H> {
+    static_nodes: HashMap<String, TrieNode<H>>,
+    dynamic_node: Option<TrieNode<H>>,
 }
 
-impl NodeChildren {
+impl<H> NodeChildren<H> {
     pub fn new() -> Box<Self> {
         Box::new(Self {
             static_nodes: HashMap::new(),
@@ -42,20 +48,27 @@ impl NodeChildren {
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct Router {
-    routes: Vec<TrieNode>,
+/// A trie of routes per method. `H` is the handler type, so WebSocket
+/// routes can share the matching logic.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+pub(crate) struct Router<H = Handler> {
+    routes: Vec<TrieNode<H>>,
+    pub(crate) names: Arc<RouteNames>,
 }
 
-impl Router {
+impl<H> Router<H> {
     pub(crate) fn new() -> Self {
-        Self { routes: Vec::new() }
+        Self {
+            routes: Vec::new(),
+            names: Arc::new(RouteNames::default()),
+        }
     }
 
     pub(crate) fn register_route(
         &mut self,
         route_str: String,
-        handler: Handler,
+        handler: H,
     ) -> Result<(), XpressError> {
         let route_def = RouteDef::try_from(route_str.as_str())?;
 
@@ -105,14 +118,19 @@ impl Router {
         &self,
         method: String,
         path: String,
-    ) -> Option<(&Handler, HashMap<String, String>)> {
+    ) -> Option<(&H, HashMap<String, String>)> {
         // Find method root
         let root = self
             .routes
             .iter()
             .find(|node| matches!(&node.route_segment, Segment::Static(m) if m == &method))?;
 
-        let segments: Vec<&str> = path[1..].split('/').filter(|s| !s.is_empty()).collect();
+        let segments: Vec<&str> = path
+            .strip_prefix('/')
+            .unwrap_or(&path)
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect();
 
         let mut cur = root;
         let mut params = HashMap::new();
@@ -140,6 +158,102 @@ impl Router {
     }
 }
 
+/// A just registered route, which can be given a name for
+/// [`Xpress::url_for`](crate::Xpress::url_for).
+pub struct Route<'a> {
+    router: &'a mut Router,
+    path: String,
+}
+
+impl<'a> Route<'a> {
+    pub(crate) fn new(router: &'a mut Router, path: &str) -> Self {
+        Self {
+            router,
+            path: path.to_string(),
+        }
+    }
+
+    /// Names the route. Panics if the name is already taken.
+    ///
+    /// ```ignore
+    /// app.get("/users/:id", show_user).name("user_detail");
+    /// ```
+    pub fn name(self, name: &str) {
+        Arc::get_mut(&mut self.router.names)
+            .expect("routes are only shared once the server is listening")
+            .insert(name, &self.path)
+            .unwrap();
+    }
+}
+
+/// The paths of named routes, for building URLs to them.
+#[derive(Debug, Default)]
+pub(crate) struct RouteNames {
+    paths: HashMap<String, Vec<Segment>>,
+}
+
+impl RouteNames {
+    fn insert(&mut self, name: &str, path: &str) -> Result<(), XpressError> {
+        if self.paths.contains_key(name) {
+            return Err(XpressError::Custom(format!(
+                "Route name {:?} is already used",
+                name
+            )));
+        }
+        let path = path.split('?').next().unwrap_or_default();
+        self.paths
+            .insert(name.to_string(), parse_path_segments(path));
+        Ok(())
+    }
+
+    /// Builds the path of a named route, filling in its parameters.
+    /// Parameters the path doesn't use are appended as the query string.
+    pub(crate) fn url_for(
+        &self,
+        name: &str,
+        params: &[(&str, &str)],
+    ) -> Result<String, XpressError> {
+        let segments = self
+            .paths
+            .get(name)
+            .ok_or_else(|| XpressError::Custom(format!("No route named {:?}", name)))?;
+
+        let mut url = String::new();
+        let mut used = Vec::new();
+        for segment in segments {
+            url.push('/');
+            match segment {
+                Segment::Static(path) => url.push_str(path),
+                Segment::Dynamic(param) => {
+                    let (key, value) =
+                        params.iter().find(|(key, _)| key == param).ok_or_else(|| {
+                            XpressError::Custom(format!(
+                                "Missing parameter {:?} for route {:?}",
+                                param, name
+                            ))
+                        })?;
+                    url.push_str(&percent_encode(value));
+                    used.push(*key);
+                }
+            }
+        }
+        if url.is_empty() {
+            url.push('/');
+        }
+
+        let query: Vec<String> = params
+            .iter()
+            .filter(|(key, _)| !used.contains(key))
+            .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+            .collect();
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query.join("&"));
+        }
+        Ok(url)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) enum Segment {
     Static(String),
@@ -187,7 +301,7 @@ impl TryFrom<&str> for RouteDef {
             )));
         };
 
-        route_def.segments = crate::parser::parse_path_segments(path_part);
+        route_def.segments = parse_path_segments(path_part);
 
         Ok(route_def)
     }
@@ -331,6 +445,7 @@ mod tests {
 
         let result = router.resolve("GET".to_string(), "/posts".to_string());
         assert!(result.is_none());
+        assert!(router.resolve("GET".to_string(), "".to_string()).is_none());
     }
 
     #[test]
@@ -458,6 +573,28 @@ mod tests {
             .is_some());
     }
 
+    #[test]
+    fn test_url_for() {
+        let mut names = RouteNames::default();
+        names.insert("user_posts", "/users/:id/posts").unwrap();
+        names.insert("home", "/").unwrap();
+        assert!(names.insert("home", "/other").is_err());
+
+        assert_eq!(
+            names.url_for("user_posts", &[("id", "a b/c")]).unwrap(),
+            "/users/a%20b%2Fc/posts"
+        );
+        assert_eq!(
+            names
+                .url_for("user_posts", &[("id", "7"), ("page", "2"), ("q", "x&y")])
+                .unwrap(),
+            "/users/7/posts?page=2&q=x%26y"
+        );
+        assert_eq!(names.url_for("home", &[]).unwrap(), "/");
+        assert!(names.url_for("user_posts", &[]).is_err());
+        assert!(names.url_for("missing", &[]).is_err());
+    }
+
     #[test]
     fn test_dynamic_param_names_preserved() {
         let mut router = Router::new();
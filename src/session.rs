@@ -0,0 +1,514 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    cookie::{Cookie, SameSite},
+    extract::FromRequest,
+    middleware::Next,
+    request::Request,
+    response::Response,
+    XpressError,
+};
+
+/// The values stored in a session.
+pub type SessionData = HashMap<String, serde_json::Value>;
+
+/// Persists session data by session ID.
+///
+/// Implementations must treat expired sessions as missing.
+pub trait SessionStore: Send + Sync + 'static {
+    fn load(&self, id: &str) -> Result<Option<SessionData>, XpressError>;
+
+    /// Stores the data, replacing any previous value, for `ttl` from now.
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), XpressError>;
+
+    fn destroy(&self, id: &str) -> Result<(), XpressError>;
+}
+
+/// How often [`MemoryStore`] drops the expired sessions nobody looked up.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keeps sessions in process memory. Expired sessions are dropped when
+/// looked up, and the rest at most once a minute as sessions are saved.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    sessions: Mutex<MemorySessions>,
+}
+
+#[derive(Debug, Default)]
+struct MemorySessions {
+    entries: HashMap<String, (SessionData, Instant)>,
+    last_sweep: Option<Instant>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Result<Option<SessionData>, XpressError> {
+        let mut sessions = self.sessions.lock()?;
+        match sessions.entries.get(id) {
+            Some((data, expires)) if *expires > Instant::now() => Ok(Some(data.clone())),
+            Some(_) => {
+                sessions.entries.remove(id);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), XpressError> {
+        let mut sessions = self.sessions.lock()?;
+        let now = Instant::now();
+        sessions
+            .entries
+            .insert(id.to_string(), (data.clone(), now + ttl));
+
+        if sessions
+            .last_sweep
+            .is_none_or(|last| now.duration_since(last) >= SWEEP_INTERVAL)
+        {
+            sessions.entries.retain(|_, (_, expires)| *expires > now);
+            sessions.last_sweep = Some(now);
+        }
+        Ok(())
+    }
+
+    fn destroy(&self, id: &str) -> Result<(), XpressError> {
+        self.sessions.lock()?.entries.remove(id);
+        Ok(())
+    }
+}
+
+/// Keeps each session in a JSON file in a directory, so sessions survive
+/// restarts.
+#[derive(Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredSession {
+    expires: SystemTime,
+    data: SessionData,
+}
+
+impl FileStore {
+    /// Uses `dir` for session files, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, XpressError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf, XpressError> {
+        // IDs come from cookies, so never let them name other files
+        if id.is_empty()
+            || !id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return Err(XpressError::bad_request("Invalid session ID"));
+        }
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> Result<Option<SessionData>, XpressError> {
+        let path = self.path(id)?;
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let stored: StoredSession = serde_json::from_slice(&contents)?;
+        if stored.expires <= SystemTime::now() {
+            let _ = fs::remove_file(path);
+            return Ok(None);
+        }
+        Ok(Some(stored.data))
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), XpressError> {
+        let path = self.path(id)?;
+        let stored = StoredSession {
+            expires: SystemTime::now() + ttl,
+            data: data.clone(),
+        };
+        // Write then rename so readers never see a partial file. The
+        // temporary name is unique so concurrent saves don't share it.
+        let tmp = self.dir.join(format!("{}.{}.tmp", id, generate_id()));
+        fs::write(&tmp, serde_json::to_vec(&stored)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    fn destroy(&self, id: &str) -> Result<(), XpressError> {
+        match fs::remove_file(self.path(id)?) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    id: Option<String>,
+    data: SessionData,
+    modified: bool,
+    regenerate: bool,
+    destroyed: bool,
+}
+
+/// The session of the current request, available through
+/// [`Request::session`] once the [`sessions`] middleware runs.
+///
+/// Changes are saved after the handler returns. Clones share the same
+/// session.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl Session {
+    fn lock(&self) -> MutexGuard<'_, SessionState> {
+        // The state holds plain data, so a panic elsewhere cannot corrupt it
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// The session ID, or `None` for a new session that has not been saved.
+    pub fn id(&self) -> Option<String> {
+        self.lock().id.clone()
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, XpressError> {
+        self.lock()
+            .data
+            .get(key)
+            .map(|value| T::deserialize(value).map_err(XpressError::JsonError))
+            .transpose()
+    }
+
+    pub fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<(), XpressError> {
+        let value = serde_json::to_value(value)?;
+        let mut state = self.lock();
+        state.data.insert(key.to_string(), value);
+        state.modified = true;
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut state = self.lock();
+        if state.data.remove(key).is_some() {
+            state.modified = true;
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.data.clear();
+        state.modified = true;
+    }
+
+    /// Moves the session to a new ID, keeping its data. Call this when the
+    /// user's privileges change, e.g. on login, to prevent session fixation.
+    pub fn regenerate(&self) {
+        let mut state = self.lock();
+        state.regenerate = true;
+        state.modified = true;
+    }
+
+    /// Deletes the session from the store and the client, e.g. on logout.
+    pub fn destroy(&self) {
+        let mut state = self.lock();
+        state.data.clear();
+        state.destroyed = true;
+    }
+}
+
+impl FromRequest for Session {
+    fn from_request(req: &Request) -> Result<Self, XpressError> {
+        req.session()
+    }
+}
+
+/// Options for the [`sessions`] middleware.
+pub struct SessionConfig {
+    store: Box<dyn SessionStore>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl SessionConfig {
+    /// Defaults to a `session_id` cookie that is `HttpOnly` and
+    /// `SameSite=Lax`, and sessions that expire after 24 hours of inactivity.
+    pub fn new(store: impl SessionStore) -> Self {
+        Self {
+            store: Box::new(store),
+            cookie_name: "session_id".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: false,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Self {
+        self.cookie_name = name.into();
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Only send the session cookie over HTTPS.
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    fn cookie(&self, id: &str) -> Cookie {
+        let cookie = Cookie::new(&self.cookie_name, id)
+            .path("/")
+            .http_only()
+            .same_site(self.same_site)
+            .max_age(self.ttl);
+        if self.secure {
+            cookie.secure()
+        } else {
+            cookie
+        }
+    }
+
+    fn persist(&self, session: &Session, res: &mut Response) -> Result<(), XpressError> {
+        let mut state = session.lock();
+
+        if state.destroyed {
+            if let Some(id) = state.id.take() {
+                self.store.destroy(&id)?;
+            }
+            return res.set_cookie(Cookie::new(&self.cookie_name, "").path("/").expire());
+        }
+
+        if state.regenerate {
+            if let Some(id) = state.id.take() {
+                self.store.destroy(&id)?;
+            }
+        }
+        // New sessions are only stored once they hold something
+        if state.id.is_none() && !state.modified {
+            return Ok(());
+        }
+
+        let id = state.id.get_or_insert_with(generate_id).clone();
+        // Saving on every request keeps active sessions alive
+        self.store.save(&id, &state.data, self.ttl)?;
+        res.set_cookie(self.cookie(&id))
+    }
+}
+
+/// A random, URL-safe identifier with 256 bits of entropy.
+pub(crate) fn generate_id() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Middleware loading the session named by the session cookie and saving it
+/// once the rest of the chain has run.
+///
+/// ```ignore
+/// app.use_middleware(sessions(SessionConfig::new(MemoryStore::new())));
+///
+/// app.post("/login", |req, res| {
+///     let session = req.session()?;
+///     session.regenerate();
+///     session.set("user_id", &42)?;
+///     res.send("Welcome")
+/// });
+/// ```
+pub fn sessions(
+    config: SessionConfig,
+) -> impl Fn(&mut Request, &mut Response, Next<'_>) -> Result<(), XpressError> + Send + Sync + 'static
+{
+    move |req, res, next| {
+        let mut state = SessionState::default();
+        if let Some(id) = req.cookie(&config.cookie_name) {
+            // Unknown, expired or malformed IDs start a fresh session
+            if let Ok(Some(data)) = config.store.load(&id) {
+                state.id = Some(id);
+                state.data = data;
+            }
+        }
+
+        let session = Session {
+            state: Arc::new(Mutex::new(state)),
+        };
+        req.extensions_mut().insert(session.clone());
+
+        // A failed request gets a fresh error response without our cookie,
+        // so nothing is stored that the client would never hear about
+        next.run(req, res)?;
+        config.persist(&session, res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::RequestBuilder, TestClient, Xpress};
+
+    fn app(store: impl SessionStore) -> TestClient {
+        let mut app = Xpress::new("127.0.0.1:0");
+        app.use_middleware(sessions(SessionConfig::new(store)));
+        app.post("/login", |req, res| {
+            let session = req.session()?;
+            session.regenerate();
+            session.set("user", &"ada")?;
+            res.send("ok")
+        });
+        app.get("/me", |req, res| {
+            let user: Option<String> = req.session()?.get("user")?;
+            res.send(user.unwrap_or_default())
+        });
+        app.post("/fail", |req, _res| {
+            let session = req.session()?;
+            session.regenerate();
+            session.set("user", &"eve")?;
+            Err(XpressError::forbidden())
+        });
+        app.post("/logout", |req, res| {
+            req.session()?.destroy();
+            res.send("bye")
+        });
+        TestClient::new(app)
+    }
+
+    fn session_cookie(res: &Response) -> Option<&Cookie> {
+        res.cookies.iter().find(|c| c.name() == "session_id")
+    }
+
+    fn get_with(client: &TestClient, path: &str, id: &str) -> Response {
+        client
+            .send(RequestBuilder::new("GET", path).header("Cookie", &format!("session_id={}", id)))
+    }
+
+    #[test]
+    fn test_new_session_is_not_stored_until_modified() {
+        let client = app(MemoryStore::new());
+        let res = client.get("/me");
+        assert!(session_cookie(&res).is_none());
+    }
+
+    #[test]
+    fn test_login_flow_with_memory_store() {
+        let client = app(MemoryStore::new());
+        let res = client.post("/login", "");
+        let id = session_cookie(&res).unwrap().value().to_string();
+        assert!(session_cookie(&res)
+            .unwrap()
+            .to_string()
+            .contains("HttpOnly"));
+
+        assert_eq!(get_with(&client, "/me", &id).body, b"ada");
+        assert_eq!(get_with(&client, "/me", "forged").body, b"");
+
+        let res = client.send(
+            RequestBuilder::new("POST", "/logout").header("Cookie", &format!("session_id={}", id)),
+        );
+        assert_eq!(session_cookie(&res).unwrap().value(), "");
+        assert_eq!(get_with(&client, "/me", &id).body, b"");
+    }
+
+    #[test]
+    fn test_regenerate_replaces_id() {
+        let client = app(MemoryStore::new());
+        let first = session_cookie(&client.post("/login", ""))
+            .unwrap()
+            .value()
+            .to_string();
+
+        let res = client.send(
+            RequestBuilder::new("POST", "/login")
+                .header("Cookie", &format!("session_id={}", first)),
+        );
+        let second = session_cookie(&res).unwrap().value().to_string();
+        assert_ne!(first, second);
+        assert_eq!(get_with(&client, "/me", &first).body, b"");
+        assert_eq!(get_with(&client, "/me", &second).body, b"ada");
+    }
+
+    #[test]
+    fn test_failed_request_is_not_persisted() {
+        let client = app(MemoryStore::new());
+        let id = session_cookie(&client.post("/login", ""))
+            .unwrap()
+            .value()
+            .to_string();
+
+        let res = client.send(
+            RequestBuilder::new("POST", "/fail").header("Cookie", &format!("session_id={}", id)),
+        );
+        assert_eq!(res.status, 403);
+        assert!(session_cookie(&res).is_none());
+        assert_eq!(get_with(&client, "/me", &id).body, b"ada");
+    }
+
+    #[test]
+    fn test_memory_store_expiry() {
+        let store = MemoryStore::new();
+        store
+            .save("a", &SessionData::new(), Duration::from_millis(0))
+            .unwrap();
+        assert!(store.load("a").unwrap().is_none());
+
+        // Sessions nobody asks for again are swept as others are saved
+        store
+            .save("b", &SessionData::new(), Duration::from_millis(0))
+            .unwrap();
+        assert!(store.sessions.lock().unwrap().entries.contains_key("b"));
+        store.sessions.lock().unwrap().last_sweep = None;
+        store
+            .save("c", &SessionData::new(), Duration::from_secs(60))
+            .unwrap();
+        let sessions = store.sessions.lock().unwrap();
+        assert_eq!(sessions.entries.keys().collect::<Vec<_>>(), ["c"]);
+    }
+
+    #[test]
+    fn test_file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStore::new(dir.path()).unwrap();
+        let mut data = SessionData::new();
+        data.insert("n".into(), 1.into());
+
+        store.save("abc", &data, Duration::from_secs(60)).unwrap();
+        assert_eq!(store.load("abc").unwrap(), Some(data));
+        assert!(store.load("../etc/passwd").is_err());
+
+        store.destroy("abc").unwrap();
+        assert_eq!(store.load("abc").unwrap(), None);
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    fmt::Write as _,
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::XpressError;
+
+/// How often a comment is sent on an idle event stream, so proxies keep the
+/// connection open and disconnected clients are noticed.
+pub(crate) const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+pub(crate) type EventsCallback =
+    Box<dyn FnOnce(&EventSender<'_>) -> Result<(), XpressError> + Send>;
+
+/// A server-sent event.
+///
+/// ```ignore
+/// sender.send(Event::new(json).event("price").id("42"))?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    retry: Option<Duration>,
+    data: String,
+}
+
+impl Event {
+    /// An event carrying `data`. Multi-line data is sent as several `data`
+    /// fields and joined again by the browser.
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Sets the ID the browser sends back in `Last-Event-ID` when it
+    /// reconnects.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the event type, dispatched to `addEventListener(type, ...)`
+    /// instead of `onmessage`.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets how long the browser waits before reconnecting.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    fn encode(&self) -> String {
+        // Line breaks would end the field early and let data inject fields
+        let single_line = |value: &str| value.replace(['\r', '\n', '\0'], "");
+
+        let mut out = String::new();
+        if let Some(id) = &self.id {
+            let _ = writeln!(out, "id: {}", single_line(id));
+        }
+        if let Some(event) = &self.event {
+            let _ = writeln!(out, "event: {}", single_line(event));
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(out, "retry: {}", retry.as_millis());
+        }
+        for line in self.data.split('\n') {
+            let _ = writeln!(out, "data: {}", line.trim_end_matches('\r'));
+        }
+        out.push('\n');
+        out
+    }
+}
+
+/// Writes events to a client, given to the callback of
+/// [`Response::sse`](crate::Response::sse).
+pub struct EventSender<'a> {
+    writer: &'a Mutex<dyn Write + Send + 'a>,
+    closed: &'a AtomicBool,
+}
+
+impl EventSender<'_> {
+    /// Sends an event, failing once the client has disconnected.
+    pub fn send(&self, event: Event) -> Result<(), XpressError> {
+        self.write(event.encode().as_bytes())
+    }
+
+    /// Sends an unnamed event with only data.
+    pub fn data(&self, data: impl Into<String>) -> Result<(), XpressError> {
+        self.send(Event::new(data))
+    }
+
+    /// Whether a write to the client has failed.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<(), XpressError> {
+        let disconnected = || XpressError::ConnectionError("Client disconnected".to_string());
+        if self.is_closed() {
+            return Err(disconnected());
+        }
+
+        let mut writer = self.writer.lock()?;
+        let result = writer.write_all(bytes).and_then(|()| writer.flush());
+        if result.is_err() {
+            self.closed.store(true, Ordering::SeqCst);
+            return Err(disconnected());
+        }
+        Ok(())
+    }
+}
+
+/// Runs an event stream callback against `writer`, sending keep-alive
+/// comments from a second thread whenever it is idle for `keep_alive`.
+pub(crate) fn run(
+    callback: EventsCallback,
+    writer: &mut (dyn Write + Send),
+    keep_alive: Duration,
+) -> Result<(), XpressError> {
+    let writer = Mutex::new(writer);
+    let closed = AtomicBool::new(false);
+    let sender = EventSender {
+        writer: &writer,
+        closed: &closed,
+    };
+    let (done, finished) = mpsc::channel::<()>();
+
+    let result = thread::scope(|scope| {
+        let sender = &sender;
+        scope.spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(keep_alive) {
+                if sender.write(b": keep-alive\n\n").is_err() {
+                    break;
+                }
+            }
+        });
+        let result = callback(sender);
+        drop(done);
+        result
+    });
+
+    // A client going away is the normal end of an event stream
+    match result {
+        Err(_) if sender.is_closed() => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_event_encoding() {
+        let event = Event::new("line 1\nline 2")
+            .id("7")
+            .event("update\nid: forged")
+            .retry(Duration::from_secs(3));
+        assert_eq!(
+            event.encode(),
+            "id: 7\nevent: updateid: forged\nretry: 3000\ndata: line 1\ndata: line 2\n\n"
+        );
+        assert_eq!(Event::new("").encode(), "data: \n\n");
+    }
+
+    #[test]
+    fn test_keep_alive_while_idle() {
+        let mut out = Vec::new();
+        run(
+            Box::new(|sender| {
+                thread::sleep(Duration::from_millis(100));
+                sender.data("hello")
+            }),
+            &mut out,
+            Duration::from_millis(10),
+        )
+        .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(": keep-alive\n\n"));
+        assert!(out.ends_with("data: hello\n\n"));
+    }
+
+    struct Disconnected;
+
+    impl Write for Disconnected {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_detects_disconnect() {
+        let result = run(
+            Box::new(|sender| {
+                assert!(!sender.is_closed());
+                assert!(sender.data("lost").is_err());
+                assert!(sender.is_closed());
+                // Handlers typically stop at the first failed send
+                loop {
+                    sender.data("tick")?;
+                }
+            }),
+            &mut Disconnected,
+            KEEP_ALIVE,
+        );
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    method::Method, middleware::Next, parser::percent_decode, request::Request, response::Response,
+    XpressError,
+};
+
+enum Resolved {
+    File(PathBuf),
+    /// A directory requested without a trailing slash.
+    Directory,
+    NotFound,
+}
+
+/// Middleware serving files below `root` for `GET` and `HEAD` requests under
+/// `prefix`. Requests that match no file continue down the chain.
+pub(crate) fn serve_static(
+    prefix: &str,
+    root: PathBuf,
+) -> impl Fn(&mut Request, &mut Response, Next<'_>) -> Result<(), XpressError> + Send + Sync + 'static
+{
+    let prefix = prefix.trim_end_matches('/').to_string();
+
+    move |req, res, next| {
+        if !matches!(req.method, Method::Get | Method::Head) {
+            return next.run(req, res);
+        }
+        let Some(rest) = strip_mount(&req.path, &prefix) else {
+            return next.run(req, res);
+        };
+
+        match resolve(&root, rest) {
+            Resolved::File(path) => res.send_file(path),
+            Resolved::Directory => {
+                // Relative links in the index page need the trailing slash.
+                // Leading slashes are collapsed, as `//host/` would send the
+                // client to another host.
+                let mut location = format!("/{}/", req.path.trim_start_matches('/'));
+                if !req.query_string.is_empty() {
+                    location = format!("{}?{}", location, req.query_string);
+                }
+                res.redirect_with(301, &location)
+            }
+            Resolved::NotFound => next.run(req, res),
+        }
+    }
+}
+
+// The part of `path` below the mount point, or `None` if it is outside.
+fn strip_mount<'p>(path: &'p str, prefix: &str) -> Option<&'p str> {
+    let rest = path.strip_prefix(prefix)?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+fn resolve(root: &Path, rest: &str) -> Resolved {
+    let mut path = root.to_path_buf();
+    for segment in rest.split('/').filter(|s| !s.is_empty()) {
+        let segment = percent_decode(segment);
+        // Rejects `.`, `..` and hidden files, and separators smuggled in
+        // through escapes
+        if segment.starts_with('.') || segment.contains(['/', '\\', '\0']) {
+            return Resolved::NotFound;
+        }
+        path.push(segment);
+    }
+
+    let Some(path) = contained(root, &path) else {
+        return Resolved::NotFound;
+    };
+    if path.is_file() {
+        return Resolved::File(path);
+    }
+    if !path.is_dir() {
+        return Resolved::NotFound;
+    }
+    if !rest.ends_with('/') {
+        return Resolved::Directory;
+    }
+    match contained(root, &path.join("index.html")) {
+        Some(index) if index.is_file() => Resolved::File(index),
+        _ => Resolved::NotFound,
+    }
+}
+
+// Resolves symlinks and checks the result is still inside `root`.
+fn contained(root: &Path, path: &Path) -> Option<PathBuf> {
+    let root = fs::canonicalize(root).ok()?;
+    let path = fs::canonicalize(path).ok()?;
+    path.starts_with(&root).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RequestBuilder, TestClient, Xpress};
+    use tempfile::TempDir;
+
+    // Builds `<tmp>/public` with some files, and a secret next to it.
+    fn fixture() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let public = dir.path().join("public");
+        fs::create_dir_all(public.join("docs")).unwrap();
+        fs::write(public.join("app.js"), "console.log(1)").unwrap();
+        fs::write(public.join("my file.txt"), "spaced").unwrap();
+        fs::write(public.join(".env"), "hidden").unwrap();
+        fs::write(public.join("docs/index.html"), "<h1>Docs</h1>").unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        dir
+    }
+
+    fn client(dir: &Path) -> TestClient {
+        let mut app = Xpress::new("127.0.0.1:0");
+        app.serve_static("/assets", dir.join("public"));
+        app.get("/assets/dynamic", |_req, res| res.send("route"));
+        TestClient::new(app)
+    }
+
+    #[test]
+    fn test_serves_files_with_mime_type() {
+        let dir = fixture();
+        let client = client(dir.path());
+
+        let res = client.get("/assets/app.js");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body, b"console.log(1)");
+        assert_eq!(
+            res.headers["Content-Type"],
+            "text/javascript; charset=utf-8"
+        );
+
+        assert_eq!(client.get("/assets/my%20file.txt").body, b"spaced");
+        assert_eq!(client.get("/assets/dynamic").body, b"route");
+    }
+
+    #[test]
+    fn test_revalidation() {
+        let dir = fixture();
+        let client = client(dir.path());
+
+        let res = client.get("/assets/app.js");
+        let etag = res.headers["ETag"].clone();
+        let last_modified = res.headers["Last-Modified"].clone();
+
+        let res = client
+            .send(RequestBuilder::new("GET", "/assets/app.js").header("If-None-Match", &etag));
+        assert_eq!(res.status, 304);
+        assert!(res.body.is_empty());
+
+        let res = client.send(
+            RequestBuilder::new("GET", "/assets/app.js")
+                .header("If-Modified-Since", &last_modified),
+        );
+        assert_eq!(res.status, 304);
+    }
+
+    #[test]
+    fn test_directories_serve_index() {
+        let dir = fixture();
+        let client = client(dir.path());
+
+        let res = client.get("/assets/docs?v=1");
+        assert_eq!(res.status, 301);
+        assert_eq!(res.headers["Location"], "/assets/docs/?v=1");
+
+        let res = client.get("/assets/docs/");
+        assert_eq!(res.body, b"<h1>Docs</h1>");
+        assert_eq!(res.headers["Content-Type"], "text/html; charset=utf-8");
+    }
+
+    #[test]
+    fn test_directory_redirect_stays_on_host() {
+        let dir = fixture();
+        fs::create_dir_all(dir.path().join("public/evil.com")).unwrap();
+        let mut app = Xpress::new("127.0.0.1:0");
+        app.serve_static("/", dir.path().join("public"));
+        let client = TestClient::new(app);
+
+        let res = client.get("//evil.com");
+        assert_eq!(res.status, 301);
+        assert_eq!(res.headers["Location"], "/evil.com/");
+    }
+
+    #[test]
+    fn test_rejects_traversal_and_hidden_files() {
+        let dir = fixture();
+        let client = client(dir.path());
+
+        for path in [
+            "/assets/../secret.txt",
+            "/assets/%2e%2e/secret.txt",
+            "/assets/..%2fsecret.txt",
+            "/assets/.env",
+            "/assetsapp.js",
+            "/assets/missing.js",
+        ] {
+            assert_eq!(client.get(path).status, 404, "{}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rejects_symlink_escape() {
+        let dir = fixture();
+        std::os::unix::fs::symlink(
+            dir.path().join("secret.txt"),
+            dir.path().join("public/link.txt"),
+        )
+        .unwrap();
+
+        assert_eq!(client(dir.path()).get("/assets/link.txt").status, 404);
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use crate::{error::XpressError, proxy_protocol};
+
+/// How long a client may take to send its PROXY protocol header.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a client may stall during the TLS handshake.
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An accepted client connection, either plaintext or wrapped in TLS.
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ServerConnection, TcpStream>>),
+}
+
+impl Stream {
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.set_read_timeout(timeout),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock.set_read_timeout(timeout),
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn tls_info(&self) -> Option<crate::tls::TlsInfo> {
+        match self {
+            Stream::Plain(_) => None,
+            Stream::Tls(tls) => Some(crate::tls::TlsInfo::from_connection(&tls.conn)),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A stream whose reading half can be lent to a request, so its body is
+/// read while the handler runs, and reclaimed to write the response.
+#[derive(Clone)]
+pub(crate) struct SharedStream(Arc<Mutex<Stream>>);
+
+impl SharedStream {
+    pub(crate) fn new(stream: Stream) -> Self {
+        Self(Arc::new(Mutex::new(stream)))
+    }
+
+    /// The stream, once every other handle has been dropped.
+    pub(crate) fn into_inner(self) -> Option<Stream> {
+        let stream = Arc::into_inner(self.0)?;
+        Some(stream.into_inner().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl Read for SharedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .read(buf)
+    }
+}
+
+/// An accepted stream along with the addresses of its endpoints.
+pub(crate) struct Connection {
+    pub(crate) stream: Stream,
+    pub(crate) remote_addr: Option<SocketAddr>,
+    pub(crate) local_addr: Option<SocketAddr>,
+}
+
+/// Turns raw TCP connections into [`Connection`]s, reading the PROXY protocol
+/// header and performing the TLS handshake when the server is configured to.
+#[derive(Clone, Default)]
+pub(crate) struct Acceptor {
+    /// Sources allowed to send PROXY protocol headers, `None` when disabled.
+    pub(crate) proxy_protocol: Option<Vec<IpAddr>>,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Acceptor {
+    pub(crate) fn accept(&self, mut stream: TcpStream) -> Result<Connection, XpressError> {
+        let mut remote_addr = stream.peer_addr().ok();
+        let mut local_addr = stream.local_addr().ok();
+
+        if let Some(trusted) = &self.proxy_protocol {
+            let peer = remote_addr.map(|addr| addr.ip());
+            if !peer.is_some_and(|ip| trusted.contains(&ip)) {
+                return Err(XpressError::ConnectionError(format!(
+                    "PROXY protocol connection from untrusted source {:?}",
+                    peer
+                )));
+            }
+
+            stream.set_read_timeout(Some(PROXY_HEADER_TIMEOUT))?;
+            let header = proxy_protocol::read_header(&mut stream)?;
+            stream.set_read_timeout(None)?;
+
+            if let Some(source) = header.source {
+                remote_addr = Some(source);
+                local_addr = header.destination;
+            }
+        }
+
+        Ok(Connection {
+            stream: self.wrap(stream)?,
+            remote_addr,
+            local_addr,
+        })
+    }
+
+    fn wrap(&self, stream: TcpStream) -> Result<Stream, XpressError> {
+        #[cfg(feature = "tls")]
+        if let Some(config) = &self.tls {
+            let mut conn = rustls::ServerConnection::new(Arc::clone(config))
+                .map_err(|e| XpressError::TlsError(e.to_string()))?;
+            let mut stream = stream;
+            // Clients that never finish the handshake would hold a worker
+            stream.set_read_timeout(Some(TLS_HANDSHAKE_TIMEOUT))?;
+            while conn.is_handshaking() {
+                conn.complete_io(&mut stream).map_err(|e| {
+                    XpressError::ConnectionError(format!("TLS handshake failed: {}", e))
+                })?;
+            }
+            stream.set_read_timeout(None)?;
+            return Ok(Stream::Tls(Box::new(rustls::StreamOwned::new(
+                conn, stream,
+            ))));
+        }
+
+        Ok(Stream::Plain(stream))
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+use minijinja::{path_loader, Environment};
+use serde::Serialize;
+
+use crate::XpressError;
+
+/// Jinja-style templates loaded from a directory, for
+/// [`Response::render`](crate::Response::render).
+///
+/// Templates are compiled on first use and cached. They can extend a
+/// layout with `{% extends "layout.html" %}`, and values are HTML-escaped
+/// in `.html`, `.htm` and `.xml` templates.
+///
+/// ```ignore
+/// app.templates(Templates::new("templates"));
+/// app.get("/users/:id", |req, res| {
+///     res.render("user.html", &json!({ "name": "Ada" }))
+/// });
+/// ```
+pub struct Templates {
+    dir: PathBuf,
+    env: Environment<'static>,
+}
+
+impl Templates {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let mut env = Environment::new();
+        env.set_loader(path_loader(&dir));
+        Self { dir, env }
+    }
+
+    /// The underlying environment, to register filters, functions or
+    /// globals.
+    pub fn environment_mut(&mut self) -> &mut Environment<'static> {
+        &mut self.env
+    }
+
+    /// Renders the template at `name`, relative to the templates directory.
+    pub fn render<S: Serialize>(&self, name: &str, context: &S) -> Result<String, XpressError> {
+        let template = self.env.get_template(name).map_err(XpressError::internal)?;
+        template.render(context).map_err(XpressError::internal)
+    }
+}
+
+impl std::fmt::Debug for Templates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Templates").field("dir", &self.dir).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TestClient, Xpress};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn templates() -> (Templates, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("layout.html"),
+            "<title>{% block title %}{% endblock %}</title><main>{% block body %}{% endblock %}</main>",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("user.html"),
+            "{% extends \"layout.html\" %}{% block title %}{{ name }}{% endblock %}\
+             {% block body %}<p>{{ bio }}</p>{% endblock %}",
+        )
+        .unwrap();
+        (Templates::new(dir.path()), dir)
+    }
+
+    #[test]
+    fn test_render_with_layout_and_escaping() {
+        let (templates, _dir) = templates();
+        let mut app = Xpress::new("127.0.0.1:0");
+        app.templates(templates);
+        app.get("/user", |_req, res| {
+            res.render(
+                "user.html",
+                &serde_json::json!({ "name": "Ada", "bio": "<script>" }),
+            )
+        });
+        app.get("/missing", |_req, res| res.render("missing.html", &()));
+        let client = TestClient::new(app);
+
+        let res = client.get("/user");
+        assert_eq!(
+            res.body,
+            b"<title>Ada</title><main><p>&lt;script&gt;</p></main>"
+        );
+        assert_eq!(res.headers["Content-Type"], "text/html; charset=utf-8");
+
+        assert_eq!(client.get("/missing").status, 500);
+    }
+}
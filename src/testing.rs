@@ -0,0 +1,207 @@
+use std::net::SocketAddr;
+
+use serde::Serialize;
+
+use crate::{method::Method, request::Request, response::Response, xpress::Xpress, XpressError};
+
+/// Builds a [`Request`] without going through the network, for use with
+/// [`TestClient`].
+///
+/// ```ignore
+/// let req = RequestBuilder::new("POST", "/users?notify=true")
+///     .header("Authorization", "Bearer token")
+///     .json(&user)?;
+/// ```
+#[derive(Debug)]
+pub struct RequestBuilder {
+    request: Request,
+}
+
+impl RequestBuilder {
+    /// Starts a request for `target`, a path with an optional query string.
+    /// A missing leading slash is added, so `users?page=2` requests
+    /// `/users?page=2`.
+    pub fn new(method: &str, target: &str) -> Self {
+        let mut request = Request {
+            method: method
+                .parse()
+                .unwrap_or_else(|_| Method::Other(method.to_string())),
+            version: "HTTP/1.1".to_string(),
+            remote_addr: Some(SocketAddr::from(([127, 0, 0, 1], 0))),
+            ..Request::default()
+        };
+        if target.starts_with('/') || target == "*" {
+            request.set_target(target);
+        } else {
+            request.set_target(&format!("/{}", target));
+        }
+        Self { request }
+    }
+
+    /// Sets a header, replacing any earlier one of the same name in any case.
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        let headers = &mut self.request.headers;
+        headers.retain(|existing, _| !existing.eq_ignore_ascii_case(key));
+        headers.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.request.body = body.into();
+        let length = self.request.body.len().to_string();
+        self.header("Content-Length", &length)
+    }
+
+    /// Serializes `body` as JSON and sets the `Content-Type` header.
+    pub fn json<T: Serialize>(self, body: &T) -> Result<Self, XpressError> {
+        Ok(self
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(body)?))
+    }
+
+    /// Sets the peer address, `127.0.0.1` by default.
+    pub fn remote_addr(mut self, addr: SocketAddr) -> Self {
+        self.request.remote_addr = Some(addr);
+        self
+    }
+
+    pub fn build(self) -> Request {
+        self.request
+    }
+}
+
+impl From<RequestBuilder> for Request {
+    fn from(builder: RequestBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// Runs requests through an app's middleware, router and error handling
+/// in-process, without binding a port.
+///
+/// ```ignore
+/// let client = TestClient::new(app);
+/// let res = client.get("/users/1");
+/// assert_eq!(res.status, 200);
+/// ```
+pub struct TestClient {
+    app: Xpress,
+}
+
+impl TestClient {
+    pub fn new(app: Xpress) -> Self {
+        Self { app }
+    }
+
+    /// Dispatches a request and returns the response the server would send.
+    /// Streamed bodies are read into [`Response::body`].
+    pub fn send(&self, request: impl Into<Request>) -> Response {
+        let mut req = request.into();
+        self.app.prepare(&mut req);
+        let mut res = self.app.dispatch(&mut req);
+        if req.method == Method::Head {
+            res.stream = None;
+            res.body.clear();
+        } else if let Err(err) = res.buffer_stream() {
+            panic!("failed to read streamed response body: {}", err);
+        }
+        res
+    }
+
+    pub fn get(&self, target: &str) -> Response {
+        self.send(RequestBuilder::new("GET", target))
+    }
+
+    pub fn post(&self, target: &str, body: impl Into<Vec<u8>>) -> Response {
+        self.send(RequestBuilder::new("POST", target).body(body))
+    }
+
+    pub fn put(&self, target: &str, body: impl Into<Vec<u8>>) -> Response {
+        self.send(RequestBuilder::new("PUT", target).body(body))
+    }
+
+    pub fn delete(&self, target: &str) -> Response {
+        self.send(RequestBuilder::new("DELETE", target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Json, Path, Query};
+    use std::collections::HashMap;
+
+    fn app() -> Xpress {
+        let mut app = Xpress::new("127.0.0.1:0");
+        app.with_state(String::from("shared")).unwrap();
+        app.use_middleware(|req, res, next| {
+            res.headers.insert("X-Middleware".into(), "1".into());
+            next.run(req, res)
+        });
+        app.route(
+            "GET",
+            "/items/:id",
+            |Path(id): Path<u32>, Query(query): Query<HashMap<String, String>>| {
+                format!("{} {}", id, query.get("sort").map_or("", String::as_str))
+            },
+        );
+        app.route("POST", "/echo", |Json(value): Json<serde_json::Value>| {
+            (201, Json(value))
+        });
+        app.get("/state", |req, res| {
+            res.send(req.state::<String>().unwrap().clone())
+        });
+        app
+    }
+
+    #[test]
+    fn test_get_runs_middleware_and_router() {
+        let res = TestClient::new(app()).get("/items/7?sort=asc");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body, b"7 asc");
+        assert_eq!(res.headers["X-Middleware"], "1");
+    }
+
+    #[test]
+    fn test_target_without_leading_slash() {
+        let client = TestClient::new(app());
+        assert_eq!(client.get("items/7?sort=asc").body, b"7 asc");
+        assert_eq!(client.get("?sort=asc").status, 404);
+
+        let req = RequestBuilder::new("GET", "?x=1").build();
+        assert_eq!(req.path(), "/");
+        assert_eq!(req.query_string(), "x=1");
+    }
+
+    #[test]
+    fn test_json_request() {
+        let req = RequestBuilder::new("POST", "/echo")
+            .json(&serde_json::json!({"name": "ada"}))
+            .unwrap();
+        let res = TestClient::new(app()).send(req);
+        assert_eq!(res.status, 201);
+        assert_eq!(res.body, br#"{"name":"ada"}"#);
+    }
+
+    #[test]
+    fn test_state_and_errors() {
+        let client = TestClient::new(app());
+        assert_eq!(client.get("/state").body, b"shared");
+        assert_eq!(client.get("/missing").status, 404);
+        assert_eq!(client.post("/echo", "not json").status, 400);
+    }
+
+    #[test]
+    fn test_client_ip_is_resolved() {
+        let mut app = Xpress::new("127.0.0.1:0");
+        app.trust_proxies(&["10.0.0.1".parse().unwrap()]);
+        app.get("/ip", |req, res| {
+            res.send(req.client_ip().unwrap().to_string())
+        });
+
+        let req = RequestBuilder::new("GET", "/ip")
+            .remote_addr("10.0.0.1:5000".parse().unwrap())
+            .header("X-Forwarded-For", "198.51.100.7");
+        assert_eq!(TestClient::new(app).send(req).body, b"198.51.100.7");
+    }
+}
@@ -1,5 +1,9 @@
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
 };
 
@@ -74,3 +78,75 @@ impl Worker {
         }
     }
 }
+
+/// Caps how many long-lived connections of one kind, such as event streams,
+/// run on their own threads outside the pool.
+#[derive(Debug)]
+pub(crate) struct ThreadLimit {
+    active: Arc<AtomicUsize>,
+    max: usize,
+}
+
+impl ThreadLimit {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            active: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    /// Reserves a thread, or `None` when `max` are already running.
+    pub(crate) fn acquire(&self) -> Option<Slot> {
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                (active < self.max).then_some(active + 1)
+            })
+            .ok()
+            .map(|_| Slot(Arc::clone(&self.active)))
+    }
+}
+
+/// A reserved thread, released when dropped.
+pub(crate) struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    /// Runs `f` on a new thread holding this slot until it returns.
+    pub(crate) fn spawn<F>(self, name: &str, f: F) -> io::Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let _slot = self;
+                f();
+            })
+            .map(drop)
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_limit() {
+        let limit = ThreadLimit::new(1);
+        let slot = limit.acquire().unwrap();
+        assert!(limit.acquire().is_none());
+
+        let (done, finished) = mpsc::channel();
+        slot.spawn("test", move || done.send(()).unwrap()).unwrap();
+        finished.recv().unwrap();
+        // The slot is released once the thread has returned
+        while limit.acquire().is_none() {
+            thread::yield_now();
+        }
+    }
+}
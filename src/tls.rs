@@ -0,0 +1,273 @@
+use std::{collections::HashMap, sync::Arc};
+
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig, ServerConnection,
+};
+
+use crate::error::XpressError;
+
+/// Certificates and protocol settings used to terminate TLS.
+///
+/// The certificate passed to [`TlsConfig::from_pem_files`] is the default one;
+/// extra certificates added with [`TlsConfig::with_sni_cert`] are selected by the
+/// server name the client sends in its hello.
+pub struct TlsConfig {
+    provider: Arc<CryptoProvider>,
+    default_cert: Arc<CertifiedKey>,
+    sni_certs: HashMap<String, Arc<CertifiedKey>>,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl TlsConfig {
+    pub fn from_pem_files(cert_path: &str, key_path: &str) -> Result<Self, XpressError> {
+        let provider = Arc::new(ring::default_provider());
+        let default_cert = load_certified_key(&provider, cert_path, key_path)?;
+
+        Ok(Self {
+            provider,
+            default_cert,
+            sni_certs: HashMap::new(),
+            alpn_protocols: vec![b"http/1.1".to_vec()],
+        })
+    }
+
+    pub fn with_sni_cert(
+        mut self,
+        server_name: &str,
+        cert_path: &str,
+        key_path: &str,
+    ) -> Result<Self, XpressError> {
+        let cert = load_certified_key(&self.provider, cert_path, key_path)?;
+        self.sni_certs
+            .insert(server_name.to_ascii_lowercase(), cert);
+        Ok(self)
+    }
+
+    pub fn with_alpn_protocols(mut self, protocols: &[&str]) -> Self {
+        self.alpn_protocols = protocols.iter().map(|p| p.as_bytes().to_vec()).collect();
+        self
+    }
+
+    pub(crate) fn build(self) -> Result<Arc<ServerConfig>, XpressError> {
+        let resolver = SniResolver {
+            default_cert: self.default_cert,
+            sni_certs: self.sni_certs,
+        };
+
+        let mut config = ServerConfig::builder_with_provider(self.provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| XpressError::TlsError(e.to_string()))?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = self.alpn_protocols;
+
+        Ok(Arc::new(config))
+    }
+}
+
+/// Details negotiated during the TLS handshake, available through
+/// [`Request::tls`](crate::request::Request::tls).
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    pub server_name: Option<String>,
+    pub alpn_protocol: Option<String>,
+    pub protocol_version: String,
+    pub cipher_suite: String,
+}
+
+impl TlsInfo {
+    pub(crate) fn from_connection(conn: &ServerConnection) -> Self {
+        Self {
+            server_name: conn.server_name().map(str::to_string),
+            alpn_protocol: conn
+                .alpn_protocol()
+                .map(|p| String::from_utf8_lossy(p).into_owned()),
+            protocol_version: conn
+                .protocol_version()
+                .map(|v| format!("{:?}", v))
+                .unwrap_or_default(),
+            cipher_suite: conn
+                .negotiated_cipher_suite()
+                .map(|s| format!("{:?}", s.suite()))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SniResolver {
+    default_cert: Arc<CertifiedKey>,
+    sni_certs: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let cert = client_hello
+            .server_name()
+            .and_then(|name| self.sni_certs.get(&name.to_ascii_lowercase()))
+            .unwrap_or(&self.default_cert);
+        Some(Arc::clone(cert))
+    }
+}
+
+fn load_certified_key(
+    provider: &CryptoProvider,
+    cert_path: &str,
+    key_path: &str,
+) -> Result<Arc<CertifiedKey>, XpressError> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| XpressError::TlsError(format!("Invalid certificate {}: {}", cert_path, e)))?;
+    if certs.is_empty() {
+        return Err(XpressError::TlsError(format!(
+            "No certificates found in {}",
+            cert_path
+        )));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| XpressError::TlsError(format!("Invalid private key {}: {}", key_path, e)))?;
+
+    let certified_key = CertifiedKey::from_der(certs, key, provider)
+        .map_err(|e| XpressError::TlsError(e.to_string()))?;
+    Ok(Arc::new(certified_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::Acceptor;
+    use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore};
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+    use tempfile::TempDir;
+
+    struct TestCert {
+        der: CertificateDer<'static>,
+        cert_path: String,
+        key_path: String,
+        // Removed with the certificate files when the cert is dropped
+        _dir: TempDir,
+    }
+
+    fn self_signed(name: &str) -> TestCert {
+        let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        let write = |file: &str, contents: String| -> String {
+            let path = dir.path().join(file);
+            std::fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+
+        TestCert {
+            der: generated.cert.der().clone(),
+            cert_path: write(&format!("{}.crt", name), generated.cert.pem()),
+            key_path: write(
+                &format!("{}.key", name),
+                generated.signing_key.serialize_pem(),
+            ),
+            _dir: dir,
+        }
+    }
+
+    // Runs a handshake against `config` and returns the server-side info and
+    // the certificate the client was presented with.
+    fn handshake(
+        config: TlsConfig,
+        trusted: &[&TestCert],
+        server_name: &str,
+    ) -> (TlsInfo, CertificateDer<'static>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = Acceptor {
+            tls: Some(config.build().unwrap()),
+            ..Acceptor::default()
+        };
+
+        let server = thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let mut stream = acceptor.accept(tcp).unwrap().stream;
+            let info = stream.tls_info().unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(b"pong").unwrap();
+            stream.flush().unwrap();
+            info
+        });
+
+        let mut roots = RootCertStore::empty();
+        for cert in trusted {
+            roots.add(cert.der.clone()).unwrap();
+        }
+        let mut client_config =
+            ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let name = ServerName::try_from(server_name.to_string()).unwrap();
+        let conn = ClientConnection::new(Arc::new(client_config), name).unwrap();
+        let mut client = rustls::StreamOwned::new(conn, TcpStream::connect(addr).unwrap());
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        let presented = client.conn.peer_certificates().unwrap()[0].clone();
+        (server.join().unwrap(), presented)
+    }
+
+    #[test]
+    fn test_default_certificate() {
+        let localhost = self_signed("localhost");
+        let config = TlsConfig::from_pem_files(&localhost.cert_path, &localhost.key_path).unwrap();
+
+        let (info, presented) = handshake(config, &[&localhost], "localhost");
+        assert_eq!(presented, localhost.der);
+        assert_eq!(info.server_name.as_deref(), Some("localhost"));
+        assert_eq!(info.alpn_protocol.as_deref(), Some("http/1.1"));
+        assert!(!info.protocol_version.is_empty());
+        assert!(!info.cipher_suite.is_empty());
+    }
+
+    #[test]
+    fn test_sni_certificate_selection() {
+        let localhost = self_signed("localhost");
+        let api = self_signed("api.xpress.test");
+        let config = TlsConfig::from_pem_files(&localhost.cert_path, &localhost.key_path)
+            .unwrap()
+            .with_sni_cert("api.xpress.test", &api.cert_path, &api.key_path)
+            .unwrap();
+
+        let (info, presented) = handshake(config, &[&localhost, &api], "api.xpress.test");
+        assert_eq!(presented, api.der);
+        assert_eq!(info.server_name.as_deref(), Some("api.xpress.test"));
+    }
+
+    #[test]
+    fn test_alpn_without_overlap() {
+        let localhost = self_signed("localhost");
+        let config = TlsConfig::from_pem_files(&localhost.cert_path, &localhost.key_path)
+            .unwrap()
+            .with_alpn_protocols(&[]);
+
+        let (info, _) = handshake(config, &[&localhost], "localhost");
+        assert_eq!(info.alpn_protocol, None);
+    }
+
+    #[test]
+    fn test_missing_certificate_file() {
+        let result = TlsConfig::from_pem_files("does/not/exist.crt", "does/not/exist.key");
+        assert!(matches!(result, Err(XpressError::TlsError(_))));
+    }
+}
@@ -0,0 +1,597 @@
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    request::Request,
+    response::{reason_phrase, Response},
+    XpressError,
+};
+
+pub(crate) type WsHandler = Arc<dyn Fn(WebSocket) -> Result<(), XpressError> + Send + Sync>;
+
+/// The largest message accepted unless the handler sets its own limit.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+/// How long a connection may stay silent before it is pinged, and then how
+/// long the ping may go unanswered before the connection is dropped.
+pub(crate) const PING_INTERVAL: Duration = Duration::from_secs(30);
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// A message received from or sent to a WebSocket client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Answered with a pong automatically before it is returned.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+
+/// The status code and reason of a close message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+/// Whether a request asks to upgrade the connection to a WebSocket.
+pub(crate) fn is_upgrade(req: &Request) -> bool {
+    let has_token = |name: &str, token: &str| {
+        req.header(name).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|item| item.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    has_token("Upgrade", "websocket") && has_token("Connection", "upgrade")
+}
+
+/// Validates the opening handshake and turns `res` into the
+/// `101 Switching Protocols` answer. Returns `false` if `res` instead asks
+/// for a protocol version this server supports.
+pub(crate) fn accept(req: &Request, res: &mut Response) -> Result<bool, XpressError> {
+    if req.header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+        // Sent directly, as error responses would drop the header
+        res.status(426);
+        res.headers
+            .insert("Sec-WebSocket-Version".to_string(), "13".to_string());
+        res.send(reason_phrase(426))?;
+        return Ok(false);
+    }
+    let key = req
+        .header("Sec-WebSocket-Key")
+        .map(str::trim)
+        .filter(|key| STANDARD.decode(key).is_ok_and(|nonce| nonce.len() == 16))
+        .ok_or_else(|| XpressError::http(400, "Invalid Sec-WebSocket-Key"))?;
+
+    res.status(101);
+    res.headers.remove("Content-Type");
+    res.headers
+        .insert("Upgrade".to_string(), "websocket".to_string());
+    res.headers
+        .insert("Connection".to_string(), "Upgrade".to_string());
+    res.headers
+        .insert("Sec-WebSocket-Accept".to_string(), accept_key(key));
+    Ok(true)
+}
+
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(GUID.as_bytes());
+    STANDARD.encode(hasher.finalize())
+}
+
+pub(crate) trait Socket: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Socket for T {}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// An upgraded connection, given to handlers registered with
+/// [`Xpress::ws`](crate::Xpress::ws).
+///
+/// ```ignore
+/// app.ws("/echo", |mut socket| {
+///     while let Some(message) = socket.recv()? {
+///         if let Message::Text(text) = message {
+///             socket.send(Message::Text(text))?;
+///         }
+///     }
+///     Ok(())
+/// });
+/// ```
+pub struct WebSocket {
+    stream: Box<dyn Socket>,
+    request: Request,
+    max_message_size: usize,
+    // A fragmented data message still waiting for its final frame
+    partial: Option<(u8, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+    ping_sent: bool,
+}
+
+impl WebSocket {
+    pub(crate) fn new(stream: impl Socket + 'static, request: Request) -> Self {
+        Self {
+            stream: Box::new(stream),
+            request,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            partial: None,
+            close_sent: false,
+            close_received: false,
+            ping_sent: false,
+        }
+    }
+
+    /// The upgrade request, with its params, headers and cookies.
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    /// Sets the largest message, after reassembling fragments, that
+    /// [`recv`](Self::recv) accepts. Larger ones close the connection with
+    /// status 1009. Defaults to 16 MiB.
+    pub fn set_max_message_size(&mut self, bytes: usize) {
+        self.max_message_size = bytes;
+    }
+
+    /// Reads the next message, or `None` once the connection is closed.
+    ///
+    /// A close from the client is answered before it is returned, and
+    /// protocol violations close the connection with the matching status
+    /// and return an error. A client silent for 30 seconds is pinged, and
+    /// dropped with an error if it stays silent for another 30.
+    pub fn recv(&mut self) -> Result<Option<Message>, XpressError> {
+        if self.close_received {
+            return Ok(None);
+        }
+        loop {
+            let Some(frame) = self.read_frame()? else {
+                return Ok(None);
+            };
+            match frame.opcode {
+                OP_CONTINUATION => {
+                    let Some((_, data)) = self.partial.as_mut() else {
+                        return self.fail(1002, "Unexpected continuation frame");
+                    };
+                    data.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        let (opcode, data) = self.partial.take().expect("checked above");
+                        return self.data_message(opcode, data).map(Some);
+                    }
+                }
+                OP_TEXT | OP_BINARY => {
+                    if self.partial.is_some() {
+                        return self.fail(1002, "Expected a continuation frame");
+                    }
+                    if frame.fin {
+                        return self.data_message(frame.opcode, frame.payload).map(Some);
+                    }
+                    self.partial = Some((frame.opcode, frame.payload));
+                }
+                OP_CLOSE => {
+                    let close = self.close_frame(&frame.payload)?;
+                    self.close_received = true;
+                    if !self.close_sent {
+                        let code = close.as_ref().map_or(1000, |close| close.code);
+                        self.write_close(code, "")?;
+                    }
+                    return Ok(Some(Message::Close(close)));
+                }
+                OP_PING => {
+                    if !self.close_sent {
+                        self.write_frame(OP_PONG, &frame.payload)?;
+                    }
+                    return Ok(Some(Message::Ping(frame.payload)));
+                }
+                OP_PONG => return Ok(Some(Message::Pong(frame.payload))),
+                _ => return self.fail(1002, "Unknown opcode"),
+            }
+        }
+    }
+
+    /// Sends a message. Nothing more can be sent after a close.
+    pub fn send(&mut self, message: Message) -> Result<(), XpressError> {
+        if self.close_sent {
+            return Err(XpressError::ConnectionError(
+                "WebSocket is closed".to_string(),
+            ));
+        }
+        match message {
+            Message::Text(text) => self.write_frame(OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => self.write_frame(OP_BINARY, &data),
+            Message::Ping(data) | Message::Pong(data) if data.len() > 125 => Err(
+                XpressError::ConnectionError("Control frame payload over 125 bytes".to_string()),
+            ),
+            Message::Ping(data) => self.write_frame(OP_PING, &data),
+            Message::Pong(data) => self.write_frame(OP_PONG, &data),
+            Message::Close(None) => {
+                self.close_sent = true;
+                self.write_frame(OP_CLOSE, &[])
+            }
+            Message::Close(Some(close)) => self.write_close(close.code, &close.reason),
+        }
+    }
+
+    /// Starts the closing handshake. Keep calling [`recv`](Self::recv) until
+    /// it returns the client's close or `None`.
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), XpressError> {
+        self.send(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.to_string(),
+        })))
+    }
+
+    fn data_message(&mut self, opcode: u8, data: Vec<u8>) -> Result<Message, XpressError> {
+        if opcode == OP_BINARY {
+            return Ok(Message::Binary(data));
+        }
+        match String::from_utf8(data) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => self.fail(1007, "Text message is not valid UTF-8"),
+        }
+    }
+
+    fn close_frame(&mut self, payload: &[u8]) -> Result<Option<CloseFrame>, XpressError> {
+        let Some((code, reason)) = payload.split_first_chunk::<2>() else {
+            if payload.is_empty() {
+                return Ok(None);
+            }
+            return self.fail(1002, "Truncated close code");
+        };
+        let code = u16::from_be_bytes(*code);
+        if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+            return self.fail(1002, "Invalid close code");
+        }
+        let Ok(reason) = std::str::from_utf8(reason) else {
+            return self.fail(1007, "Close reason is not valid UTF-8");
+        };
+        Ok(Some(CloseFrame {
+            code,
+            reason: reason.to_string(),
+        }))
+    }
+
+    fn read_frame(&mut self) -> Result<Option<Frame>, XpressError> {
+        let mut head = [0u8; 2];
+        loop {
+            match self.stream.read(&mut head[..1]) {
+                // The client going away between frames ends the connection
+                // normally
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                // The read timeout ran out, see `PING_INTERVAL`
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if self.ping_sent {
+                        if !self.close_sent {
+                            let _ = self.write_close(1001, "Ping timed out");
+                        }
+                        self.close_received = true;
+                        return Err(XpressError::ConnectionError(
+                            "WebSocket client stopped responding".to_string(),
+                        ));
+                    }
+                    self.write_frame(OP_PING, &[])?;
+                    self.ping_sent = true;
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        self.ping_sent = false;
+        self.stream.read_exact(&mut head[1..])?;
+
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        if head[0] & 0x70 != 0 {
+            return self.fail(1002, "Reserved bits set without an extension");
+        }
+        if head[1] & 0x80 == 0 {
+            return self.fail(1002, "Client frames must be masked");
+        }
+
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0u8; 2];
+                self.stream.read_exact(&mut len)?;
+                u64::from(u16::from_be_bytes(len))
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                self.stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => u64::from(len),
+        };
+
+        let is_control = opcode & 0x8 != 0;
+        if is_control && (!fin || len > 125) {
+            return self.fail(1002, "Control frames must be final and at most 125 bytes");
+        }
+        // Checked before reading so a huge length cannot exhaust memory
+        let buffered = self.partial.as_ref().map_or(0, |(_, data)| data.len());
+        let remaining = self.max_message_size.saturating_sub(buffered);
+        if !is_control && len > remaining as u64 {
+            return self.fail(1009, "Message too big");
+        }
+
+        let mut mask = [0u8; 4];
+        self.stream.read_exact(&mut mask)?;
+        let mut payload = vec![0u8; len as usize];
+        self.stream.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<(), XpressError> {
+        // Server frames are final and never masked
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | opcode);
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    fn write_close(&mut self, code: u16, reason: &str) -> Result<(), XpressError> {
+        // The reason has to fit a control frame along with the code
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.close_sent = true;
+        self.write_frame(OP_CLOSE, &payload)
+    }
+
+    fn fail<T>(&mut self, code: u16, reason: &str) -> Result<T, XpressError> {
+        if !self.close_sent {
+            let _ = self.write_close(code, reason);
+        }
+        self.close_received = true;
+        Err(XpressError::ConnectionError(format!(
+            "WebSocket protocol error: {reason}"
+        )))
+    }
+}
+
+impl std::fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("path", &self.request.path)
+            .field("max_message_size", &self.max_message_size)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RequestBuilder, TestClient, Xpress};
+    use std::io::Cursor;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_handshake() {
+        // The example from RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let mut app = Xpress::new("127.0.0.1:0");
+        app.ws("/chat", |_socket| Ok(()));
+        let client = TestClient::new(app);
+        let upgrade = |version: &str| {
+            client.send(
+                RequestBuilder::new("GET", "/chat")
+                    .header("Upgrade", "websocket")
+                    .header("Connection", "keep-alive, Upgrade")
+                    .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+                    .header("Sec-WebSocket-Version", version),
+            )
+        };
+
+        let res = upgrade("13");
+        assert_eq!(res.status, 101);
+        assert_eq!(res.headers["Upgrade"], "websocket");
+        assert_eq!(
+            res.headers["Sec-WebSocket-Accept"],
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert!(res.upgrade.is_some());
+
+        let res = upgrade("8");
+        assert_eq!(res.status, 426);
+        assert_eq!(res.headers["Sec-WebSocket-Version"], "13");
+        let res = client.get("/chat");
+        assert_eq!(res.status, 426);
+        assert_eq!(res.headers["Upgrade"], "websocket");
+    }
+
+    struct MockSocket {
+        input: Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+        // Times out instead of reporting the end of the input
+        idle: bool,
+    }
+
+    impl Read for MockSocket {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.input.read(buf)? {
+                0 if self.idle => Err(ErrorKind::TimedOut.into()),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl Write for MockSocket {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    fn socket(frames: &[Vec<u8>]) -> (WebSocket, Arc<Mutex<Vec<u8>>>) {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let stream = MockSocket {
+            input: Cursor::new(frames.concat()),
+            output: Arc::clone(&output),
+            idle: false,
+        };
+        (WebSocket::new(stream, Request::default()), output)
+    }
+
+    #[test]
+    fn test_messages() {
+        let (mut socket, output) = socket(&[
+            client_frame(false, OP_TEXT, "héllo, ".as_bytes()),
+            client_frame(true, OP_PING, b"hi"),
+            client_frame(true, OP_CONTINUATION, b"world"),
+            client_frame(true, OP_BINARY, &[0; 300]),
+            client_frame(true, OP_CLOSE, &[0x03, 0xE8, b'b', b'y', b'e']),
+        ]);
+
+        // Control frames may arrive between fragments
+        assert_eq!(socket.recv().unwrap(), Some(Message::Ping(b"hi".to_vec())));
+        assert_eq!(
+            socket.recv().unwrap(),
+            Some(Message::Text("héllo, world".to_string()))
+        );
+        assert_eq!(socket.recv().unwrap(), Some(Message::Binary(vec![0; 300])));
+        socket.send(Message::Text("ok".to_string())).unwrap();
+        assert_eq!(
+            socket.recv().unwrap(),
+            Some(Message::Close(Some(CloseFrame {
+                code: 1000,
+                reason: "bye".to_string()
+            })))
+        );
+        assert_eq!(socket.recv().unwrap(), None);
+        assert!(socket.send(Message::Text("late".to_string())).is_err());
+
+        // Pong, text and the close reply, all unmasked
+        assert_eq!(
+            *output.lock().unwrap(),
+            [
+                &[0x8A, 2, b'h', b'i'][..],
+                &[0x81, 2, b'o', b'k'],
+                &[0x88, 2, 0x03, 0xE8],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_pings_idle_client() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let stream = MockSocket {
+            input: Cursor::new(client_frame(true, OP_PONG, b"")),
+            output: Arc::clone(&output),
+            idle: true,
+        };
+        let mut socket = WebSocket::new(stream, Request::default());
+
+        assert_eq!(socket.recv().unwrap(), Some(Message::Pong(Vec::new())));
+        // One timeout sends a ping, the next gives up on the client
+        assert!(socket.recv().is_err());
+        assert_eq!(socket.recv().unwrap(), None);
+        assert_eq!(
+            *output.lock().unwrap(),
+            [&[0x89, 0, 0x88, 16, 0x03, 0xE9][..], b"Ping timed out"].concat()
+        );
+    }
+
+    fn close_code(frames: &[Vec<u8>], max_message_size: usize) -> u16 {
+        let (mut socket, output) = socket(frames);
+        socket.set_max_message_size(max_message_size);
+        assert!(socket.recv().is_err());
+        let output = output.lock().unwrap();
+        assert_eq!(output[0], 0x88);
+        u16::from_be_bytes([output[2], output[3]])
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        let mut unmasked = client_frame(true, OP_TEXT, b"hi");
+        unmasked[1] &= 0x7F;
+        assert_eq!(close_code(&[unmasked], 1024), 1002);
+        assert_eq!(
+            close_code(&[client_frame(true, OP_CONTINUATION, b"hi")], 1024),
+            1002
+        );
+        assert_eq!(
+            close_code(&[client_frame(false, OP_PING, b"hi")], 1024),
+            1002
+        );
+        assert_eq!(
+            close_code(&[client_frame(true, OP_TEXT, &[0xFF, 0xFE])], 1024),
+            1007
+        );
+        // The limit applies to the reassembled message
+        assert_eq!(
+            close_code(
+                &[
+                    client_frame(false, OP_BINARY, &[0; 6]),
+                    client_frame(true, OP_CONTINUATION, &[0; 6]),
+                ],
+                10
+            ),
+            1009
+        );
+    }
+}
@@ -1,16 +1,52 @@
-use crate::request::Request;
-use crate::response::Response;
-use crate::thread_pool::ThreadPool;
-use crate::{error::XpressError, router::Router};
-use std::sync::Arc;
+use crate::conditional;
+use crate::cookie_jar::{CookieKeys, Key};
+use crate::extensions::Extensions;
+use crate::extract::HandlerFn;
+use crate::method::Method;
+use crate::middleware::{Middleware, Next};
+use crate::range;
+use crate::request::{Request, DEFAULT_MAX_BODY_SIZE};
+use crate::response::{reason_phrase, Response, StreamBody};
+use crate::router::{Handler, Route};
+use crate::sse;
+use crate::static_files::serve_static;
+use crate::stream::{Acceptor, Connection, SharedStream};
+use crate::thread_pool::{ThreadLimit, ThreadPool};
+use crate::websocket::{self, WebSocket, WsHandler};
+use crate::{
+    error::{default_error_handler, ErrorHandler, XpressError},
+    router::Router,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::{
-    io::{BufReader, Write},
-    net::{TcpListener, TcpStream},
+    io::{self, BufReader, Read, Write},
+    net::{IpAddr, TcpListener},
 };
 
+/// Default cap on event streams and on WebSockets, each on its own thread.
+const DEFAULT_MAX_STREAMS: usize = 1024;
+
 pub struct Xpress {
     address: String,
     router: Router,
+    ws_router: Router<WsHandler>,
+    middleware: Vec<Middleware>,
+    state: Arc<Extensions>,
+    error_handler: Option<ErrorHandler>,
+    status_handlers: HashMap<u16, Handler>,
+    trusted_proxies: Vec<IpAddr>,
+    cookie_keys: Option<Arc<CookieKeys>>,
+    proxy_protocol: Option<Vec<IpAddr>>,
+    etag: bool,
+    max_body_size: usize,
+    event_streams: ThreadLimit,
+    websockets: ThreadLimit,
+    #[cfg(feature = "tls")]
+    tls: Option<crate::tls::TlsConfig>,
+    #[cfg(feature = "template")]
+    templates: Option<Arc<crate::template::Templates>>,
 }
 
 impl Xpress {
@@ -18,20 +54,164 @@ impl Xpress {
         Self {
             address: address.to_string(),
             router: Router::new(),
+            ws_router: Router::new(),
+            middleware: Vec::new(),
+            state: Arc::new(Extensions::new()),
+            error_handler: None,
+            status_handlers: HashMap::new(),
+            trusted_proxies: Vec::new(),
+            cookie_keys: None,
+            proxy_protocol: None,
+            etag: true,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            event_streams: ThreadLimit::new(DEFAULT_MAX_STREAMS),
+            websockets: ThreadLimit::new(DEFAULT_MAX_STREAMS),
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "template")]
+            templates: None,
         }
     }
 
-    pub fn listen(self) -> Result<(), XpressError> {
+    /// Serves every connection over TLS using the given certificates.
+    #[cfg(feature = "tls")]
+    pub fn tls(&mut self, config: crate::tls::TlsConfig) {
+        self.tls = Some(config);
+    }
+
+    /// Sets the templates used by [`Response::render`].
+    #[cfg(feature = "template")]
+    pub fn templates(&mut self, templates: crate::template::Templates) {
+        self.templates = Some(Arc::new(templates));
+    }
+
+    /// Trusts `X-Forwarded-For` and `Forwarded` headers on connections coming
+    /// from these addresses when resolving [`Request::client_ip`].
+    pub fn trust_proxies(&mut self, proxies: &[IpAddr]) {
+        self.trusted_proxies.extend_from_slice(proxies);
+    }
+
+    /// Sets the key for signed and encrypted cookies (see
+    /// [`CookieJar`](crate::CookieJar)). Cookies sealed with one of the
+    /// `previous` keys are still accepted, so keys can be rotated without
+    /// logging everyone out.
+    pub fn cookie_keys(&mut self, current: Key, previous: Vec<Key>) {
+        self.cookie_keys = Some(Arc::new(CookieKeys { current, previous }));
+    }
+
+    /// Requires every connection to start with a PROXY protocol (v1 or v2)
+    /// header and only accepts connections from the given load balancers.
+    /// The client address announced in the header becomes the request's
+    /// [`Request::remote_addr`].
+    pub fn proxy_protocol(&mut self, trusted_sources: &[IpAddr]) {
+        self.proxy_protocol = Some(trusted_sources.to_vec());
+    }
+
+    /// Enables or disables the weak `ETag` generated for successful `GET`
+    /// and `HEAD` responses that don't set one. Enabled by default.
+    /// Conditional request headers are evaluated either way.
+    pub fn etag(&mut self, enabled: bool) {
+        self.etag = enabled;
+    }
+
+    /// Sets the largest request body accepted, in bytes. Requests declaring
+    /// a longer `Content-Length` are answered with `413 Content Too Large`
+    /// before any of the body is read. Defaults to 50 MiB.
+    ///
+    /// Multipart bodies are streamed to [`Request::multipart`] instead, and
+    /// bounded by its limits.
+    pub fn max_body_size(&mut self, bytes: usize) {
+        self.max_body_size = bytes;
+    }
+
+    /// Sets how many event streams may be open at once. Each runs on its
+    /// own thread, and requests past the limit are answered with
+    /// `503 Service Unavailable`. Defaults to 1024.
+    pub fn max_event_streams(&mut self, limit: usize) {
+        self.event_streams = ThreadLimit::new(limit);
+    }
+
+    /// Sets how many WebSockets may be open at once. Each runs on its own
+    /// thread, and upgrades past the limit are answered with
+    /// `503 Service Unavailable`. Defaults to 1024.
+    pub fn max_websockets(&mut self, limit: usize) {
+        self.websockets = ThreadLimit::new(limit);
+    }
+
+    /// Stores a value shared by every request, retrieved in handlers with
+    /// [`Request::state`]. One value is kept per type. Fails if a request
+    /// still holds the state from an earlier dispatch.
+    pub fn with_state<T: Send + Sync + 'static>(&mut self, state: T) -> Result<(), XpressError> {
+        Arc::get_mut(&mut self.state)
+            .ok_or_else(|| {
+                XpressError::Custom("State cannot change while requests hold it".to_string())
+            })?
+            .insert(Arc::new(state));
+        Ok(())
+    }
+
+    /// Adds a middleware that runs, in registration order, before the route
+    /// handler. See [`Next`] for how to continue or short-circuit the chain.
+    pub fn use_middleware<F>(&mut self, middleware: F)
+    where
+        F: Fn(&mut Request, &mut Response, Next<'_>) -> Result<(), XpressError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    /// Serves the files below `dir` under the URL path `prefix`, e.g.
+    /// `app.serve_static("/assets", "./public")` maps `/assets/app.js` to
+    /// `./public/app.js`. Directories serve their `index.html`.
+    ///
+    /// Paths escaping `dir`, through `..` or symlinks, and hidden files are
+    /// never served. Requests matching no file fall through to later
+    /// middleware and routes.
+    pub fn serve_static(&mut self, prefix: &str, dir: impl Into<PathBuf>) {
+        self.use_middleware(serve_static(prefix, dir.into()));
+    }
+
+    /// Replaces the default error rendering. The hook receives every error
+    /// returned by middleware or handlers, with the response status already
+    /// set from [`XpressError::status_code`]. Use
+    /// [`problem_json`](crate::problem_json) for RFC 7807 responses.
+    pub fn on_error<F>(&mut self, handler: F)
+    where
+        F: Fn(&XpressError, &Request, &mut Response) -> Result<(), XpressError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.error_handler = Some(Box::new(handler));
+    }
+
+    /// Renders errors with the given status, e.g. a custom 404 page. Takes
+    /// precedence over [`Xpress::on_error`].
+    pub fn on_status<F>(&mut self, status: u16, handler: F)
+    where
+        F: Fn(&Request, &mut Response) -> Result<(), XpressError> + Send + Sync + 'static,
+    {
+        self.status_handlers.insert(status, Box::new(handler));
+    }
+
+    pub fn listen(mut self) -> Result<(), XpressError> {
         let listener = TcpListener::bind(&self.address)?;
         let pool = ThreadPool::new(num_cpus::get());
-        let router = Arc::new(self.router);
+        let acceptor = Arc::new(self.acceptor()?);
+        let app = Arc::new(self);
 
-        for stream in listener.incoming() {
+        for (connection_id, stream) in (1..).zip(listener.incoming()) {
             let stream = stream?;
-            let router = Arc::clone(&router);
+            let app = Arc::clone(&app);
+            let acceptor = Arc::clone(&acceptor);
 
             pool.execute(move || {
-                if let Err(err) = Self::handle_connection(stream, &router) {
+                let result = acceptor
+                    .accept(stream)
+                    .and_then(|connection| app.handle_connection(connection, connection_id));
+                if let Err(err) = result {
                     eprintln!("Connection error: {}", err);
                 }
             });
@@ -39,105 +219,618 @@ impl Xpress {
         Ok(())
     }
 
-    fn handle_connection(mut stream: TcpStream, router: &Router) -> Result<(), XpressError> {
-        let request = {
-            let mut buf_reader = BufReader::new(&mut stream);
-            match Request::try_from(&mut buf_reader) {
-                Ok(req) => Some(req),
-                Err(e) => {
-                    eprintln!("Request parsing error: {}", e);
-                    None
-                }
+    fn acceptor(&mut self) -> Result<Acceptor, XpressError> {
+        Ok(Acceptor {
+            proxy_protocol: self.proxy_protocol.take(),
+            #[cfg(feature = "tls")]
+            tls: self
+                .tls
+                .take()
+                .map(crate::tls::TlsConfig::build)
+                .transpose()?,
+        })
+    }
+
+    fn handle_connection(
+        &self,
+        connection: Connection,
+        connection_id: u64,
+    ) -> Result<(), XpressError> {
+        let Connection {
+            stream,
+            remote_addr,
+            local_addr,
+        } = connection;
+        #[cfg(feature = "tls")]
+        let tls = stream.tls_info();
+        // Shared so a multipart body can be streamed to its handler
+        let shared = SharedStream::new(stream);
+        let request = Request::parse_streaming(BufReader::new(shared.clone()), self.max_body_size);
+        let reclaim = |shared: SharedStream| {
+            shared.into_inner().ok_or_else(|| {
+                XpressError::ConnectionError("Request body still borrowed".to_string())
+            })
+        };
+        let request = match request {
+            Ok(req) => Some(req),
+            Err(err @ XpressError::Http(_)) => {
+                // Rejected by a limit, so the client is told why
+                let mut resp = self.render_error(&err, &Request::default());
+                resp.headers
+                    .insert("Connection".to_string(), "close".to_string());
+                return Self::send_response(resp, &mut reclaim(shared)?, true);
+            }
+            Err(e) => {
+                eprintln!("Request parsing error: {}", e);
+                None
             }
         };
 
         if let Some(mut req) = request {
-            let mut response = Response::new();
-            let result = (|| {
-                let Some((handler, params)) = router.resolve(req.method.clone(), req.path.clone())
-                else {
-                    return Err(XpressError::NotFound(format!(
-                        "{} {}",
-                        req.method, req.path
-                    )));
-                };
-                req.params = params;
-                handler(&req, &mut response)?;
-                Ok(response)
-            })();
-
-            let mut resp = match result {
-                Ok(resp) => resp,
-                Err(err) => {
-                    let mut resp = Response::new();
-                    resp.status = err.status_code();
-                    resp.body = format!("Error: {}", err).into();
-                    resp
-                }
-            };
+            #[cfg(feature = "tls")]
+            {
+                req.tls = tls;
+            }
+            req.connection_id = connection_id;
+            req.remote_addr = remote_addr;
+            req.local_addr = local_addr;
+            self.prepare(&mut req);
 
+            let mut resp = self.dispatch(&mut req);
+            // Whatever the handler left of the body goes with the connection
+            req.body_reader = Mutex::default();
+            let mut stream = reclaim(shared)?;
+            if let Some(handler) = resp.upgrade.take() {
+                let Some(slot) = self.websockets.acquire() else {
+                    let resp = self.unavailable(&req, "Too many open WebSockets");
+                    return Self::send_response(resp, &mut stream, true);
+                };
+                Self::send_response(resp, &mut stream, false)?;
+                // Lets the socket notice peers that went away without closing
+                stream.set_read_timeout(Some(websocket::PING_INTERVAL))?;
+                // Like event streams, sockets live outside the pool
+                slot.spawn("xpress-ws", move || {
+                    if let Err(err) = handler(WebSocket::new(stream, req)) {
+                        eprintln!("WebSocket error: {}", err);
+                    }
+                })?;
+                return Ok(());
+            }
             resp.headers
                 .insert("Connection".to_string(), "close".to_string());
 
-            Self::send_response(resp, &mut stream)?;
+            let include_body = req.method != Method::Head;
+            if resp.events.is_some() && include_body {
+                match self.event_streams.acquire() {
+                    Some(slot) => {
+                        // Event streams stay open indefinitely, so they get
+                        // their own thread rather than holding a pool worker
+                        slot.spawn("xpress-sse", move || {
+                            if let Err(err) = Self::send_response(resp, &mut stream, true) {
+                                eprintln!("Event stream error: {}", err);
+                            }
+                        })?;
+                        return Ok(());
+                    }
+                    None => resp = self.unavailable(&req, "Too many open event streams"),
+                }
+            }
+            Self::send_response(resp, &mut stream, include_body)?;
         }
 
         Ok(())
     }
 
-    fn send_response(response: Response, stream: &mut TcpStream) -> Result<(), XpressError> {
-        let headers = format!(
-            "HTTP/1.1 {}\r\n{}\r\nContent-Length: {}\r\n\r\n",
+    /// Fills in request details derived from the server configuration.
+    pub(crate) fn prepare(&self, req: &mut Request) {
+        req.client_ip = req.resolve_client_ip(&self.trusted_proxies);
+        req.cookie_keys = self.cookie_keys.clone();
+    }
+
+    /// Runs a request through the middleware chain and its route handler.
+    pub(crate) fn dispatch(&self, req: &mut Request) -> Response {
+        req.state = Arc::clone(&self.state);
+        req.routes = Arc::clone(&self.router.names);
+
+        let endpoint = |req: &mut Request, res: &mut Response| {
+            let socket = self
+                .ws_router
+                .resolve(req.method.to_string(), req.path.clone());
+            if let Some((handler, params)) = &socket {
+                if websocket::is_upgrade(req) {
+                    req.params = params.clone();
+                    if websocket::accept(req, res)? {
+                        res.upgrade = Some(Arc::clone(handler));
+                    }
+                    return Ok(());
+                }
+            }
+
+            let Some((handler, params)) = self
+                .router
+                .resolve(req.method.to_string(), req.path.clone())
+            else {
+                if socket.is_some() {
+                    res.status(426);
+                    res.headers
+                        .insert("Upgrade".to_string(), "websocket".to_string());
+                    return res.send(reason_phrase(426));
+                }
+                return Err(XpressError::NotFound(format!(
+                    "{} {}",
+                    req.method, req.path
+                )));
+            };
+            req.params = params;
+            handler(req, res)
+        };
+
+        let mut response = self.new_response();
+        match Next::new(&self.middleware, &endpoint).run(req, &mut response) {
+            Ok(()) => {
+                conditional::apply(req, &mut response, self.etag);
+                match range::apply(req, &mut response) {
+                    Ok(()) => response,
+                    Err(err) => self.render_error(&err, req),
+                }
+            }
+            Err(err) => self.render_error(&err, req),
+        }
+    }
+
+    /// The `503` sent when a long-lived connection would exceed its limit.
+    fn unavailable(&self, req: &Request, message: &str) -> Response {
+        let mut res = self.render_error(&XpressError::http(503, message), req);
+        res.headers
+            .insert("Connection".to_string(), "close".to_string());
+        res
+    }
+
+    fn new_response(&self) -> Response {
+        Response {
+            #[cfg(feature = "template")]
+            templates: self.templates.clone(),
+            ..Response::new()
+        }
+    }
+
+    /// Builds the response for a failed request, preferring a handler for the
+    /// error's status, then the `on_error` hook, then the default renderer.
+    fn render_error(&self, err: &XpressError, req: &Request) -> Response {
+        let status = err.status_code();
+        if status >= 500 {
+            let mut message = err.to_string();
+            let mut source = std::error::Error::source(err);
+            while let Some(cause) = source {
+                message.push_str(&format!(": {}", cause));
+                source = cause.source();
+            }
+            eprintln!("Error handling {} {}: {}", req.method, req.path, message);
+        }
+
+        let mut res = self.new_response();
+        res.status = status;
+        let result = match (self.status_handlers.get(&status), &self.error_handler) {
+            (Some(handler), _) => handler(req, &mut res),
+            (None, Some(handler)) => handler(err, req, &mut res),
+            (None, None) => default_error_handler(err, req, &mut res),
+        };
+
+        if let Err(handler_err) = result {
+            eprintln!("Error handler failed: {}", handler_err);
+            res = Response::new();
+            res.status = status;
+            let _ = default_error_handler(err, req, &mut res);
+        }
+        res
+    }
+
+    fn send_response(
+        mut response: Response,
+        stream: &mut (impl Write + Send),
+        include_body: bool,
+    ) -> Result<(), XpressError> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
             response.status,
-            response
-                .headers
-                .iter()
-                .map(|(k, v)| format!("{}: {}", k, v))
-                .collect::<Vec<_>>()
-                .join("\r\n"),
-            response.body.len()
+            reason_phrase(response.status)
         );
+        for (key, value) in &response.headers {
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        for cookie in &response.cookies {
+            head.push_str(&format!("Set-Cookie: {}\r\n", cookie));
+        }
+
+        // These statuses never carry a body, nor a length for one
+        let bodiless = matches!(response.status, 100..=199 | 204 | 304);
+        let body = response.stream.take().filter(|_| !bodiless);
+        if let Some(events) = response.events.take().filter(|_| !bodiless) {
+            // The event stream has no length and ends when the connection
+            // closes
+            head.push_str("\r\n");
+            stream.write_all(head.as_bytes())?;
+            stream.flush()?;
+            if include_body {
+                sse::run(events, stream, sse::KEEP_ALIVE)?;
+            }
+            return Ok(());
+        }
+        match &body {
+            Some(StreamBody {
+                length: Some(length),
+                ..
+            }) => head.push_str(&format!("Content-Length: {}\r\n\r\n", length)),
+            Some(StreamBody { length: None, .. }) => {
+                head.push_str("Transfer-Encoding: chunked\r\n\r\n")
+            }
+            None if bodiless => head.push_str("\r\n"),
+            None => head.push_str(&format!("Content-Length: {}\r\n\r\n", response.body.len())),
+        }
+        stream.write_all(head.as_bytes())?;
 
-        stream.write_all(headers.as_bytes())?;
-        stream.write_all(&response.body)?;
+        if include_body && !bodiless {
+            match body {
+                Some(StreamBody {
+                    reader,
+                    length: Some(length),
+                    ..
+                }) => {
+                    io::copy(&mut reader.take(length), stream)?;
+                }
+                Some(StreamBody {
+                    mut reader,
+                    length: None,
+                    ..
+                }) => {
+                    let mut chunk = [0; 8 * 1024];
+                    loop {
+                        let read = reader.read(&mut chunk)?;
+                        write!(stream, "{:X}\r\n", read)?;
+                        if read == 0 {
+                            stream.write_all(b"\r\n")?;
+                            break;
+                        }
+                        stream.write_all(&chunk[..read])?;
+                        stream.write_all(b"\r\n")?;
+                    }
+                }
+                None => stream.write_all(&response.body)?,
+            }
+        }
         stream.flush()?;
 
         Ok(())
     }
 
-    pub fn get<F>(&mut self, path: &str, handler: F)
+    /// Builds the path of a route named with [`Route::name`], percent-encoding
+    /// the parameters. Parameters not used by the path become the query
+    /// string. Handlers can use [`Request::url_for`] instead.
+    ///
+    /// ```ignore
+    /// app.get("/users/:id", show_user).name("user_detail");
+    /// assert_eq!(app.url_for("user_detail", &[("id", "42")])?, "/users/42");
+    /// ```
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, XpressError> {
+        self.router.names.url_for(name, params)
+    }
+
+    /// Registers a handler for any method. Accepts every signature supported
+    /// by [`HandlerFn`], including functions that take extractors and return
+    /// an [`IntoResponse`](crate::IntoResponse):
+    ///
+    /// ```ignore
+    /// app.route("GET", "/users/:id", |Path(id): Path<u32>| (200, Json(find_user(id))));
+    /// ```
+    pub fn route<Args, H>(&mut self, method: &str, path: &str, handler: H) -> Route<'_>
+    where
+        H: HandlerFn<Args>,
+        Args: 'static,
+    {
+        self.router
+            .register_route(
+                format!("{method} {path}"),
+                Box::new(move |req, res| handler.call(req, res)),
+            )
+            .unwrap();
+        Route::new(&mut self.router, path)
+    }
+
+    pub fn get<F>(&mut self, path: &str, handler: F) -> Route<'_>
     where
         F: Fn(&Request, &mut Response) -> Result<(), XpressError> + Send + Sync + 'static,
     {
         self.router
             .register_route(format!("GET {path}"), Box::new(handler))
             .unwrap();
+        Route::new(&mut self.router, path)
     }
 
-    pub fn post<F>(&mut self, path: &str, handler: F)
+    pub fn post<F>(&mut self, path: &str, handler: F) -> Route<'_>
     where
         F: Fn(&Request, &mut Response) -> Result<(), XpressError> + Send + Sync + 'static,
     {
         self.router
             .register_route(format!("POST {path}"), Box::new(handler))
             .unwrap();
+        Route::new(&mut self.router, path)
     }
 
-    pub fn put<F>(&mut self, path: &str, handler: F)
+    pub fn put<F>(&mut self, path: &str, handler: F) -> Route<'_>
     where
         F: Fn(&Request, &mut Response) -> Result<(), XpressError> + Send + Sync + 'static,
     {
         self.router
             .register_route(format!("PUT {path}"), Box::new(handler))
             .unwrap();
+        Route::new(&mut self.router, path)
     }
 
-    pub fn delete<F>(&mut self, path: &str, handler: F)
+    pub fn delete<F>(&mut self, path: &str, handler: F) -> Route<'_>
     where
         F: Fn(&Request, &mut Response) -> Result<(), XpressError> + Send + Sync + 'static,
     {
         self.router
             .register_route(format!("DELETE {path}"), Box::new(handler))
             .unwrap();
+        Route::new(&mut self.router, path)
+    }
+
+    /// Accepts WebSocket connections at `path`. The handler runs on its own
+    /// thread once the handshake is done, after the middleware chain has
+    /// accepted the upgrade request. Plain requests to the path get
+    /// `426 Upgrade Required` unless another route handles them.
+    ///
+    /// ```ignore
+    /// app.ws("/chat/:room", |mut socket| {
+    ///     let room = socket.request().params["room"].clone();
+    ///     while let Some(message) = socket.recv()? {
+    ///         // ...
+    ///     }
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn ws<F>(&mut self, path: &str, handler: F)
+    where
+        F: Fn(WebSocket) -> Result<(), XpressError> + Send + Sync + 'static,
+    {
+        self.ws_router
+            .register_route(format!("GET {path}"), Arc::new(handler))
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str) -> Request {
+        Request {
+            method: method.parse().unwrap(),
+            path: path.to_string(),
+            ..Request::default()
+        }
+    }
+
+    fn app() -> Xpress {
+        let mut app = Xpress::new("127.0.0.1:0");
+        app.get("/boom", |_req, _res| {
+            Err(XpressError::Custom("secret connection string".to_string()))
+        });
+        app
+    }
+
+    #[test]
+    fn test_redirect_to_named_route() {
+        let mut app = app();
+        app.get("/users/:id", |_req, res| res.send("user"))
+            .name("user_detail");
+        app.post("/users", |req, res| {
+            res.redirect_with(303, &req.url_for("user_detail", &[("id", "42")])?)
+        });
+        assert_eq!(
+            app.url_for("user_detail", &[("id", "4 2")]).unwrap(),
+            "/users/4%202"
+        );
+
+        let res = app.dispatch(&mut request("POST", "/users"));
+        assert_eq!(res.status, 303);
+        assert_eq!(res.headers["Location"], "/users/42");
+
+        let mut res = Response::new();
+        assert!(res.redirect("/a\r\nSet-Cookie: x=1").is_err());
+        assert!(res.redirect_with(200, "/a").is_err());
+    }
+
+    #[test]
+    fn test_url_for_round_trips_through_extractors() {
+        use crate::{Path, Query};
+
+        #[derive(serde::Deserialize)]
+        struct Search {
+            q: String,
+        }
+
+        let mut app = app();
+        app.route(
+            "GET",
+            "/files/:name",
+            |Path(name): Path<String>, Query(search): Query<Search>| format!("{name}|{}", search.q),
+        )
+        .name("file");
+
+        let url = app
+            .url_for("file", &[("name", "a b/ü+"), ("q", "x&y=z é+")])
+            .unwrap();
+        let mut req = request("GET", "");
+        req.set_target(&url);
+        let res = app.dispatch(&mut req);
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body, "a b/ü+|x&y=z é+".as_bytes());
+    }
+
+    #[test]
+    fn test_with_state() {
+        let mut app = app();
+        app.with_state(String::from("shared")).unwrap();
+        app.get("/state", |req, res| {
+            res.send(req.state::<String>().unwrap().clone())
+        });
+        assert_eq!(app.dispatch(&mut request("GET", "/state")).body, b"shared");
+
+        let held = Arc::clone(&app.state);
+        assert!(app.with_state(1u8).is_err());
+        drop(held);
+        assert!(app.with_state(1u8).is_ok());
+    }
+
+    #[test]
+    fn test_default_error_redacts_server_errors() {
+        let res = app().dispatch(&mut request("GET", "/boom"));
+        assert_eq!(res.status, 500);
+        assert_eq!(res.body, b"Internal Server Error");
+    }
+
+    #[test]
+    fn test_default_error_shows_client_errors() {
+        let res = app().dispatch(&mut request("GET", "/missing"));
+        assert_eq!(res.status, 404);
+        assert_eq!(res.body, b"Error: Route Not Found: GET /missing");
+    }
+
+    #[test]
+    fn test_status_handler_renders_custom_page() {
+        let mut app = app();
+        app.on_status(404, |req, res| res.send(format!("No page at {}", req.path)));
+        app.on_error(|_err, _req, res| res.send("generic"));
+
+        let res = app.dispatch(&mut request("GET", "/missing"));
+        assert_eq!(res.status, 404);
+        assert_eq!(res.body, b"No page at /missing");
+
+        let res = app.dispatch(&mut request("GET", "/boom"));
+        assert_eq!(res.body, b"generic");
+    }
+
+    #[test]
+    fn test_error_hook_receives_error() {
+        let mut app = app();
+        app.on_error(|err, _req, res| {
+            res.status(503);
+            res.send(format!("{}", err.status_code()))
+        });
+
+        let res = app.dispatch(&mut request("GET", "/boom"));
+        assert_eq!(res.status, 503);
+        assert_eq!(res.body, b"500");
+    }
+
+    #[test]
+    fn test_failing_error_hook_falls_back_to_default() {
+        let mut app = app();
+        app.on_error(|_err, _req, _res| Err(XpressError::Custom("hook failed".to_string())));
+
+        let res = app.dispatch(&mut request("GET", "/missing"));
+        assert_eq!(res.status, 404);
+        assert_eq!(res.body, b"Error: Route Not Found: GET /missing");
+    }
+
+    #[test]
+    fn test_send_response_writes_each_cookie() {
+        let mut res = Response::new();
+        res.set_cookie(crate::Cookie::new("a", "1")).unwrap();
+        res.clear_cookie("b").unwrap();
+
+        let mut out = Vec::new();
+        Xpress::send_response(res, &mut out, true).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.contains("\r\nSet-Cookie: a=1\r\n"));
+        assert!(out.contains("\r\nSet-Cookie: b=; Path=/; Max-Age=0; "));
+        assert!(out.ends_with("Content-Length: 0\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_send_response_writes_event_stream() {
+        let mut res = Response::new();
+        res.sse(|sender| {
+            sender.send(crate::Event::new("1").id("a"))?;
+            sender.data("2")
+        });
+
+        let mut out = Vec::new();
+        Xpress::send_response(res, &mut out, true).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Content-Type: text/event-stream\r\n"));
+        assert!(!out.contains("Content-Length"));
+        assert!(out.ends_with("\r\n\r\nid: a\ndata: 1\n\ndata: 2\n\n"));
+    }
+
+    #[test]
+    fn test_send_response_streams_chunked_body() {
+        let mut res = Response::new();
+        res.stream(&b"hello world"[..]);
+
+        let mut out = Vec::new();
+        Xpress::send_response(res, &mut out, true).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!out.contains("Content-Length"));
+        assert!(out.ends_with("\r\n\r\nB\r\nhello world\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_problem_json_hook() {
+        let mut app = app();
+        app.on_error(crate::problem_json);
+
+        let res = app.dispatch(&mut request("GET", "/boom"));
+        assert_eq!(res.status, 500);
+        assert_eq!(res.headers["Content-Type"], "application/problem+json");
+    }
+
+    #[test]
+    fn test_multipart_upload_streams_from_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = dir.path().to_path_buf();
+        let mut app = app();
+        // Far below the upload, which must therefore not be buffered
+        app.max_body_size(1024);
+        app.post("/upload", move |req, res| {
+            let form = req.multipart()?.spool_files_to(&spool).form_data()?;
+            let file = form.file("f").unwrap();
+            res.send(format!("{} {}", req.body().len(), file.size()))
+        });
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let connection = Acceptor::default().accept(tcp).unwrap();
+            app.handle_connection(connection, 1).unwrap();
+        });
+
+        let part = vec![b'x'; 512 * 1024];
+        let part_head =
+            "--b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"f.bin\"\r\n\r\n";
+        let tail = "\r\n--b--\r\n";
+        let mut client = std::net::TcpStream::connect(addr).unwrap();
+        write!(
+            client,
+            "POST /upload HTTP/1.1\r\n\
+             Content-Type: multipart/form-data; boundary=b\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            part_head.len() + part.len() + tail.len(),
+            part_head
+        )
+        .unwrap();
+        client.write_all(&part).unwrap();
+        client.write_all(tail.as_bytes()).unwrap();
+
+        let mut out = String::new();
+        client.read_to_string(&mut out).unwrap();
+        server.join().unwrap();
+        assert!(out.starts_with("HTTP/1.1 200"), "{}", out);
+        assert!(out.ends_with("\r\n\r\n0 524288"), "{}", out);
     }
 }